@@ -1,63 +1,204 @@
+extern crate alloc;
+use alloc::collections::BTreeMap;
+use core::num::{NonZeroU32, NonZeroUsize};
 use std::{
-    collections::HashMap,
-    fs::File,
-    io::{self, Write, stdout},
+    collections::HashSet,
+    io::{Write, stdout},
     path::PathBuf,
 };
 
+use ::calendar::{DEFAULT_CALSCALE, DEFAULT_PRODID, RenderOptions, parse_calscale, parse_color};
+use chrono::{DateTime, Days, Duration, NaiveDate, Utc};
 use clap::{Parser as ClapParser, Subcommand, ValueEnum};
-use tracing::{debug, info, trace};
+use common::Output;
+use tracing::{debug, info, trace, warn};
 use uuid::Uuid;
 
 use crate::{
     repository::{
-        Repository, WritableRepository as _,
+        EventFilter, Repository, RepositoryError, WritableRepository as _,
         sqlite::{open_readonly_repository, open_writable_repository},
     },
-    types::{Calendar, Event, EventException},
+    types::{Calendar, Event, EventException, RecurrenceRange},
 };
 
 pub mod repository;
 pub mod types;
 
-#[derive(Debug, Clone, ValueEnum)]
-enum OutputFormat {
-    Ical,
-    Json,
-}
-
 #[derive(ClapParser, Debug, Default)]
 pub struct OutputArg {
-    /// File path, print to stdout if omitted
+    /// File path, print to stdout if omitted or `-`
     #[arg(long)]
     output: Option<PathBuf>,
 }
 
+/// Output formats of `export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExportFormat {
+    Ical,
+    /// An array with each calendar and its events, also for a single
+    /// calendar
+    Json,
+    /// One line of JSON per event, with its exceptions
+    Jsonl,
+}
+
 #[derive(ClapParser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct CalendarArgs {
-    /// File path, print to stdout if omitted
+    /// File path, print to stdout if omitted or `-`
     #[clap(flatten)]
     output: OutputArg,
 
     /// Output format
-    #[arg(value_enum, long, default_value_t = OutputFormat::Ical)]
-    format: OutputFormat,
+    #[arg(value_enum, long, default_value_t = ExportFormat::Ical)]
+    format: ExportFormat,
+
+    /// Database id, repeat to merge several calendars into one
+    #[arg(long, required = true)]
+    id: Vec<Uuid>,
+
+    /// Leave out calendars that are not found instead of failing
+    #[arg(long)]
+    skip_missing: bool,
+
+    /// Omit non-standard X- properties
+    #[arg(long)]
+    strict_rfc: bool,
+
+    /// Transliterate æøå and drop other non-ASCII characters in calendar and event text
+    #[arg(long)]
+    ascii: bool,
+
+    /// Truncate event summaries longer than this many characters
+    #[arg(long)]
+    max_summary_len: Option<NonZeroUsize>,
+
+    /// Omit DTEND on single-day events
+    #[arg(long)]
+    no_dtend: bool,
+
+    /// Omit optional properties, like DESCRIPTION, URL and X- properties,
+    /// for a smaller calendar
+    #[arg(long)]
+    compact: bool,
+
+    /// Break event descriptions at word boundaries to at most this many
+    /// characters per line
+    #[arg(long, value_name = "COLS")]
+    wrap_description: Option<NonZeroUsize>,
+
+    /// Write events lasting several days as one event per day
+    #[arg(long)]
+    flatten_multiday: bool,
+
+    /// Check the calendar for empty UIDs or summaries and bad dates, and
+    /// fail instead of writing it
+    #[arg(long)]
+    validate: bool,
+
+    /// Print iCalendar output unfolded and indented, with every line
+    /// commented out, for reading. Not valid iCalendar
+    #[arg(long)]
+    explain: bool,
+
+    /// Product identifier of the calendar
+    #[arg(long, default_value = DEFAULT_PRODID)]
+    prodid: String,
+
+    /// Calendar scale written as CALSCALE
+    #[arg(long, value_name = "VALUE", default_value = DEFAULT_CALSCALE, value_parser = parse_calscale)]
+    calscale: String,
 
-    /// Database id
+    /// Omit CALSCALE, for tools that reject it
+    #[arg(long, conflicts_with = "calscale")]
+    no_calscale: bool,
+
+    /// Fail on events with an invalid RRULE instead of exporting them
+    /// without recurrence
     #[arg(long)]
-    id: Uuid,
+    strict: bool,
+
+    /// Write the first N occurrences of each recurring event as separate
+    /// events instead of an RRULE (ical format only)
+    #[arg(long, value_name = "N")]
+    expand: Option<NonZeroUsize>,
+
+    /// Only export events starting on or after this date. Recurring events
+    /// starting earlier are left out entirely
+    #[arg(long, value_name = "DATE", value_parser = ::calendar::parse_flexible_date)]
+    after: Option<NaiveDate>,
+
+    /// Only export events with a SEQUENCE greater than N, for syncing the
+    /// events changed since the last push. Unrelated to `list --since`,
+    /// which looks at when calendars were last modified
+    #[arg(long, value_name = "N")]
+    since_sequence: Option<u32>,
+
+    /// Leave out events more than this long ago, given in days or weeks
+    /// like `90d` or `4w`. Recurring events start at their first occurrence
+    /// since then instead (ical format only)
+    #[arg(long, value_name = "DURATION", value_parser = parse_age)]
+    drop_older_than: Option<Days>,
+}
+
+/// Parse a number of days, like `90d`, or weeks, like `4w`.
+fn parse_age(value: &str) -> Result<Days, String> {
+    let (n, days_per_unit) = if let Some(n) = value.strip_suffix('d') {
+        (n, 1)
+    } else if let Some(n) = value.strip_suffix('w') {
+        (n, 7)
+    } else {
+        return Err("expected a number of days or weeks, like 90d or 4w".to_string());
+    };
+    let n: u64 = n
+        .parse()
+        .map_err(|err: core::num::ParseIntError| err.to_string())?;
+    // Longer than any date chrono can represent is surely a typo
+    let max = (NaiveDate::MAX - NaiveDate::MIN).num_days().unsigned_abs();
+    n.checked_mul(days_per_unit)
+        .filter(|days| *days <= max)
+        .map(Days::new)
+        .ok_or_else(|| format!("{value} is too long"))
 }
 
 impl CalendarArgs {
-    fn out(&self) -> Result<Box<dyn Write>, io::Error> {
-        let writer: Box<dyn Write> = match &self.output.output {
-            Some(path) => Box::new(File::create(path)?),
-            None => Box::new(stdout().lock()),
-        };
-        Ok(writer)
+    fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            strict_rfc: self.strict_rfc,
+            ascii: self.ascii,
+            max_summary_len: self.max_summary_len,
+            no_dtend: self.no_dtend,
+            wrap_description: self.wrap_description,
+            compact: self.compact,
+            ..RenderOptions::default()
+        }
+    }
+
+    fn calscale(&self) -> Option<String> {
+        (!self.no_calscale).then(|| self.calscale.clone())
+    }
+
+    const fn event_filter(&self) -> EventFilter {
+        EventFilter {
+            after: self.after,
+            since_sequence: self.since_sequence,
+        }
+    }
+
+    fn output(&self) -> Output {
+        Output::new(self.output.output.as_deref())
     }
 }
 
+/// A calendar as shown by the `list` command.
+#[derive(Debug, serde::Serialize)]
+struct CalendarListing {
+    #[serde(flatten)]
+    calendar: Calendar,
+    event_count: usize,
+}
+
 #[derive(ClapParser, Debug)]
 pub struct DatabaseArg {
     #[arg(long, env = "RIZWOLD_CALENDAR_DB")]
@@ -80,17 +221,51 @@ pub enum Commands {
     List {
         #[clap(flatten)]
         database_arg: DatabaseArg,
+        /// Only calendars modified at or after this RFC 3339 timestamp
+        #[arg(long)]
+        since: Option<DateTime<Utc>>,
+        /// List at most this many calendars. The output is an object with
+        /// the `calendars` and the `total` count without paging
+        #[arg(long, value_name = "N")]
+        limit: Option<u32>,
+        /// Skip this many calendars
+        #[arg(long, value_name = "M")]
+        offset: Option<u32>,
+    },
+    /// Create an empty calendar and print its id
+    Create {
+        #[clap(flatten)]
+        database_arg: DatabaseArg,
+        /// Calendar name
+        #[arg(long)]
+        name: String,
+        /// Calendar description
+        #[arg(long)]
+        description: Option<String>,
+        /// Database id, generated if omitted
+        #[arg(long)]
+        id: Option<Uuid>,
+        /// Calendar color, a CSS3 color name
+        #[arg(long, value_parser = parse_color)]
+        color: Option<String>,
+        /// Suggested refresh interval for subscribers, in seconds
+        #[arg(long)]
+        refresh_interval: Option<NonZeroU32>,
     },
 }
 
-fn get_calendar(repo: &impl Repository, cal: Calendar) -> anyhow::Result<::calendar::Calendar> {
+fn get_calendar(
+    repo: &impl Repository,
+    cal: Calendar,
+    filter: EventFilter,
+) -> anyhow::Result<::calendar::Calendar> {
     let cal_id = cal.id;
     let mut collector = EventCollector::new(cal);
 
-    repo.for_each_event(Some(cal_id), |evt| {
+    repo.for_each_event(Some(cal_id), filter, |evt| {
         let evt_id = evt.id;
         let has_rrule = evt.rrule.is_some();
-        debug!("Processing event {}", evt_id);
+        debug!(event = %evt_id, "Processing event");
         trace!("{evt:?}");
         collector.process_event(evt);
         if has_rrule {
@@ -104,9 +279,15 @@ fn get_calendar(repo: &impl Repository, cal: Calendar) -> anyhow::Result<::calen
     Ok(collector.finalize())
 }
 
-fn export_json(repo: &impl Repository, cal: &Calendar, out: impl Write) -> anyhow::Result<()> {
+type JsonCalendar = (Calendar, Vec<(Event, Vec<EventException>)>);
+
+fn json_calendar(
+    repo: &impl Repository,
+    cal: Calendar,
+    filter: EventFilter,
+) -> anyhow::Result<JsonCalendar> {
     let mut events = Vec::new();
-    repo.for_each_event(Some(cal.id), |evt| {
+    repo.for_each_event(Some(cal.id), filter, |evt| {
         let mut x = (evt.clone(), Vec::new());
         repo.for_each_event_exceptions(Some(evt.id), |ex| {
             x.1.push(ex);
@@ -115,33 +296,125 @@ fn export_json(repo: &impl Repository, cal: &Calendar, out: impl Write) -> anyho
         events.push(x);
         Ok(())
     })?;
-    let data = (cal, events);
-    serde_json::ser::to_writer(out, &data)?;
-    Ok(())
+    Ok((cal, events))
 }
 
-fn export(
+/// Write each event in `cal` with its exceptions as a line of JSON, one
+/// event at a time.
+fn write_json_lines(
     repo: &impl Repository,
-    cal_id: Uuid,
-    format: &OutputFormat,
-    out: impl Write,
+    cal: &Calendar,
+    filter: EventFilter,
+    out: &mut impl Write,
 ) -> anyhow::Result<()> {
-    match repo.get_calendar(cal_id)? {
-        None => Err(anyhow::format_err!("calendar not found")),
-        Some(cal) => {
-            debug!("Found calendar {cal:?}");
-            match format {
-                OutputFormat::Ical => {
-                    let calendar = get_calendar(repo, cal)?;
-                    calendar.write(out)?;
-                }
-                OutputFormat::Json => {
-                    export_json(repo, &cal, out)?;
-                }
-            }
+    repo.for_each_event(Some(cal.id), filter, |evt| {
+        let mut exceptions = Vec::new();
+        repo.for_each_event_exceptions(Some(evt.id), |ex| {
+            exceptions.push(ex);
             Ok(())
+        })?;
+        serde_json::ser::to_writer(&mut *out, &(evt, exceptions))?;
+        out.write_all(b"\n")?;
+        Ok(())
+    })
+}
+
+/// Export the calendars in `args`.
+///
+/// Several calendars are merged into one, named after the first, see
+/// [`::calendar::Calendar::merge`]. As JSON they are written as an array,
+/// and as JSON lines the events of all of them follow each other.
+fn export(repo: &impl Repository, args: &CalendarArgs, mut out: impl Write) -> anyhow::Result<()> {
+    let mut calendars = Vec::new();
+    for id in &args.id {
+        match repo.get_calendar(*id)? {
+            Some(cal) => {
+                debug!("Found calendar {cal:?}");
+                calendars.push(cal);
+            }
+            None if args.skip_missing => warn!(calendar = %id, "Calendar not found, skipping"),
+            None => return Err(anyhow::format_err!("calendar not found: {id}")),
+        }
+    }
+    let Some((first, rest)) = calendars.split_first() else {
+        return Err(anyhow::format_err!("calendar not found"));
+    };
+    match args.format {
+        ExportFormat::Ical => {
+            let mut calendar = get_calendar(repo, first.clone(), args.event_filter())?;
+            for cal in rest {
+                calendar.merge(get_calendar(repo, cal.clone(), args.event_filter())?);
+            }
+            if let Some(age) = args.drop_older_than {
+                let today = Utc::now().date_naive();
+                calendar.drop_before(today.checked_sub_days(age).unwrap_or(NaiveDate::MIN));
+            }
+            if let Some(limit) = args.expand {
+                calendar.expand_recurring(limit.get());
+            }
+            if args.flatten_multiday {
+                calendar.flatten_multiday();
+            }
+            calendar.options = args.render_options();
+            args.prodid.clone_into(&mut calendar.prodid);
+            calendar.calscale = args.calscale();
+            if args.validate {
+                calendar.is_valid().map_err(|problems| {
+                    anyhow::format_err!("invalid calendar: {}", problems.join("; "))
+                })?;
+            }
+            if args.explain {
+                warn!("Output is a debug view, not iCalendar");
+                out.write_all(::calendar::explain(&calendar.to_string()).as_bytes())?;
+            } else {
+                calendar.write(out)?;
+            }
+        }
+        ExportFormat::Json => {
+            let mut seen = HashSet::new();
+            let calendars = calendars
+                .into_iter()
+                .filter(|cal| seen.insert(cal.id))
+                .map(|cal| json_calendar(repo, cal, args.event_filter()))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            serde_json::ser::to_writer(out, &calendars)?;
+        }
+        ExportFormat::Jsonl => {
+            let mut seen = HashSet::new();
+            for cal in calendars.iter().filter(|cal| seen.insert(cal.id)) {
+                write_json_lines(repo, cal, args.event_filter(), &mut out)?;
+            }
         }
     }
+    Ok(())
+}
+
+/// Write the calendars modified at or after `since` as JSON, with their
+/// event counts and the total count without `limit` and `offset`.
+fn list(
+    repo: &impl Repository,
+    since: Option<DateTime<Utc>>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+    out: impl Write,
+) -> anyhow::Result<()> {
+    let calendars = repo
+        .list_calendars(since, limit, offset.unwrap_or(0))?
+        .into_iter()
+        .map(|calendar| {
+            let event_count = repo.count_events(calendar.id)?;
+            Ok(CalendarListing {
+                calendar,
+                event_count,
+            })
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let total = repo.count_calendars(since)?;
+    serde_json::ser::to_writer(
+        out,
+        &serde_json::json!({ "total": total, "calendars": calendars }),
+    )?;
+    Ok(())
 }
 
 impl Commands {
@@ -154,20 +427,50 @@ impl Commands {
                 repo.migrate()
             }
             Self::Export { database_arg, args } => {
-                info!("Open database {}", database_arg.database.display());
-                let repo = open_readonly_repository(database_arg.database)?;
-                export(&repo, args.id, &args.format, args.out()?)
+                info!(database = %database_arg.database.display(), "Open database");
+                let repo = open_readonly_repository(database_arg.database, args.strict)?;
+                let mut out = args.output().create()?;
+                export(&repo, &args, &mut out)?;
+                Ok(out.commit()?)
             }
-            Self::List { database_arg } => {
-                info!("Open database {}", database_arg.database.display());
-                let repo = open_readonly_repository(database_arg.database)?;
-                let mut xs: Vec<Calendar> = vec![];
-                repo.for_each_calendar(|cal| {
-                    xs.push(cal);
-                    Ok(())
-                })?;
-                let out = stdout().lock();
-                serde_json::ser::to_writer(out, &xs)?;
+            Self::List {
+                database_arg,
+                since,
+                limit,
+                offset,
+            } => {
+                info!(database = %database_arg.database.display(), "Open database");
+                let repo = open_readonly_repository(database_arg.database, false)?;
+                list(&repo, since, limit, offset, stdout().lock())
+            }
+            Self::Create {
+                database_arg,
+                name,
+                description,
+                id,
+                color,
+                refresh_interval,
+            } => {
+                info!(database = %database_arg.database.display(), "Open database");
+                let mut repo = open_writable_repository(database_arg.database)?;
+                let now = Utc::now();
+                let cal = Calendar {
+                    id: id.unwrap_or_else(Uuid::now_v7),
+                    name,
+                    description,
+                    created_at: now,
+                    last_modified: now,
+                    color,
+                    refresh_interval,
+                };
+                repo.insert_calendar(&cal)
+                    .map_err(|err| match err.downcast_ref() {
+                        Some(RepositoryError::Conflict(id)) => {
+                            anyhow::format_err!("calendar already exists: {id}")
+                        }
+                        _ => err,
+                    })?;
+                writeln!(stdout(), "{}", cal.id)?;
                 Ok(())
             }
         }
@@ -177,52 +480,92 @@ impl Commands {
 #[derive(Debug)]
 struct EventCollector {
     calendar: Calendar,
-    // Vi bruker en Map for raskt oppslag på master-events
-    masters: HashMap<Uuid, ::calendar::Event>,
+    // Vi bruker en Map for raskt oppslag på master-events, sortert på uid
+    // så rekkefølgen ikke avhenger av hashing
+    masters: BTreeMap<Uuid, ::calendar::Event>,
     // En liste for unntakene (som blir egne VEVENTs)
     exceptions: Vec<::calendar::Event>,
 }
 impl EventCollector {
     #[must_use]
-    pub fn new(calendar: Calendar) -> Self {
+    pub const fn new(calendar: Calendar) -> Self {
         Self {
             calendar,
-            masters: HashMap::new(),
+            masters: BTreeMap::new(),
             exceptions: Vec::new(),
         }
     }
 
+    /// Add `db_event` as a master event.
+    ///
+    /// Events the builder rejects, such as those with a blank summary, are
+    /// skipped with a warning, so they are not exported.
     pub fn process_event(&mut self, db_event: Event) {
         // Konverterer DB-rad til domene-Event (master)
-        let event = ::calendar::Event {
-            uid: db_event.id,
-            dtstamp: db_event.last_modified,
-            date: db_event.dtstart_initial,
-            summary: db_event.summary,
-            description: db_event.description,
-            rrule: db_event.rrule, // Allerede parset
-            sequence: i64::from(db_event.sequence),
-            duration: db_event.duration_days,
-            rdates: Vec::new(),
-            exdates: Vec::new(),
-            url: db_event.url.map(Into::into),
-            recurrence_id: None,
-        };
-        self.masters.insert(event.uid, event);
+        let event = ::calendar::Event::builder(
+            db_event.id,
+            db_event.dtstart_initial,
+            db_event.summary,
+            db_event.last_modified,
+        )
+        .description(db_event.description)
+        .rrule(db_event.rrule) // Allerede parset
+        .sequence(i64::from(db_event.sequence))
+        .duration(db_event.duration_days)
+        .url(db_event.url.map(Into::into))
+        .build();
+        match event {
+            Ok(event) => {
+                self.masters.insert(event.uid, event);
+            }
+            Err(err) => warn!("Skipping event: {err}"),
+        }
     }
 
+    /// Apply `ex` to its master event, as an EXDATE, an override or the end
+    /// of the series.
+    ///
+    /// Like events in [`EventCollector::process_event`], exceptions with a
+    /// blank new summary are skipped with a warning, keeping the original
+    /// occurrence.
     pub fn process_exception(&mut self, ex: EventException) {
+        if ex
+            .new_summary
+            .as_ref()
+            .is_some_and(|summary| summary.trim().is_empty())
+        {
+            warn!(
+                "Skipping exception: Event {} on {} has an empty summary",
+                ex.event_id, ex.original_date
+            );
+            return;
+        }
+        let this_and_future = ex.range == Some(RecurrenceRange::ThisAndFuture);
+        let is_override = ex.new_date.is_some() || ex.new_summary.is_some();
+        if this_and_future && !is_override {
+            // Sletting av denne og alle senere forekomster: avslutt serien
+            if let Some(master) = self.masters.remove(&ex.event_id)
+                && let Some(master) = master.ending_before(ex.original_date)
+            {
+                self.masters.insert(master.uid, master);
+            }
+            return;
+        }
         if let Some(master) = self.masters.get_mut(&ex.event_id) {
-            // 1. Legg originaldatoen i masterens EXDATE
-            master.exdates.push(ex.original_date);
+            // 1. Legg originaldatoen i masterens EXDATE. Med THISANDFUTURE
+            // erstattes forekomsten i stedet, så den skal ikke utelates.
+            if !this_and_future {
+                master.exdates.push(ex.original_date);
+            }
 
             // 2. Hvis unntaket ikke bare er en sletting (altså har new_date eller new_summary)
             // lag et nytt VEVENT som peker tilbake til master via RECURRENCE-ID
-            if ex.new_date.is_some() || ex.new_summary.is_some() {
+            if is_override {
                 let mut exception_event = master.clone();
 
                 // Overskriv verdier
                 exception_event.recurrence_id = Some(ex.original_date);
+                exception_event.this_and_future = this_and_future;
                 exception_event.date = ex.new_date.unwrap_or(ex.original_date);
 
                 if let Some(s) = ex.new_summary {
@@ -245,12 +588,438 @@ impl EventCollector {
     pub fn finalize(self) -> ::calendar::Calendar {
         let mut all_events = self.masters.into_values().collect::<Vec<_>>();
         all_events.extend(self.exceptions);
+        // Sort by start date so the export does not depend on the order rows
+        // and exceptions were read in. A master comes before its exceptions
+        // on the same date.
+        all_events.sort_by_key(|event| (event.date, event.uid, event.recurrence_id));
         // all_events
         ::calendar::Calendar {
             name: Some(self.calendar.name),
             description: self.calendar.description,
-            prodid: "-//Rizwold//Calendar//NO".to_string(),
+            prodid: DEFAULT_PRODID.to_string(),
             events: all_events,
+            options: ::calendar::RenderOptions::default(),
+            attendees: Vec::new(),
+            organizer: None,
+            color: self.calendar.color,
+            refresh_interval: self
+                .calendar
+                .refresh_interval
+                .map(|secs| Duration::seconds(i64::from(secs.get()))),
+            calscale: Some(DEFAULT_CALSCALE.to_string()),
+        }
+    }
+}
+
+/// An empty database in memory, for tests.
+#[cfg(test)]
+fn test_repo() -> repository::sqlite::Sqlite3Repo {
+    let mut repo =
+        repository::sqlite::Sqlite3Repo::new(rusqlite::Connection::open_in_memory().unwrap());
+    repo.migrate().unwrap();
+    repo
+}
+
+/// A calendar named `name`, for tests.
+#[cfg(test)]
+fn test_calendar(name: &str) -> Calendar {
+    let now = Utc::now();
+    Calendar {
+        id: Uuid::now_v7(),
+        name: name.to_string(),
+        description: None,
+        created_at: now,
+        last_modified: now,
+        color: None,
+        refresh_interval: None,
+    }
+}
+
+/// A single day event on `date` without rules, for tests.
+#[cfg(test)]
+fn test_event(calendar_id: Uuid, summary: &str, date: NaiveDate) -> Event {
+    let now = Utc::now();
+    Event {
+        id: Uuid::now_v7(),
+        calendar_id,
+        summary: summary.to_string(),
+        description: None,
+        url: None,
+        dtstart_initial: date,
+        duration_days: core::num::NonZeroU8::MIN,
+        rrule: None,
+        sequence: 0,
+        created_at: now,
+        last_modified: now,
+    }
+}
+
+/// Parse `rrule` for a series starting on `date`, for tests.
+#[cfg(test)]
+fn test_rrule(rrule: &str, date: NaiveDate) -> rrule::RRule {
+    let dt_start = date.and_time(chrono::NaiveTime::MIN).and_utc();
+    rrule
+        .parse::<rrule::RRule<rrule::Unvalidated>>()
+        .unwrap()
+        .validate(dt_start.with_timezone(&rrule::Tz::UTC))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_export_merged() {
+        let mut repo = test_repo();
+        let today = Utc::now().date_naive();
+        let mut ids = Vec::new();
+        for name in ["Garbage", "Post"] {
+            let cal = test_calendar(name);
+            repo.insert_calendar(&cal).unwrap();
+            repo.insert_events(&[test_event(cal.id, name, today)])
+                .unwrap();
+            ids.push(cal.id.to_string());
+        }
+        let missing = Uuid::now_v7().to_string();
+        let export = |extra: &[&str]| {
+            let mut args = vec!["export"];
+            for id in &ids {
+                args.extend(["--id", id]);
+            }
+            args.extend(extra);
+            let mut buf = Vec::new();
+            export(
+                &repo,
+                &CalendarArgs::try_parse_from(args).unwrap(),
+                &mut buf,
+            )
+            .map(|()| String::from_utf8(buf).unwrap())
+        };
+
+        let ics = export(&["--id", &ids[0]]).unwrap();
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ics.contains("SUMMARY:Garbage\r\n"));
+        assert!(ics.contains("SUMMARY:Post\r\n"));
+        assert!(ics.contains("\r\nNAME:Garbage\r\n"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&export(&["--format", "json"]).unwrap()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 2);
+
+        assert!(export(&["--id", &missing]).is_err());
+        let ics = export(&["--id", &missing, "--skip-missing"]).unwrap();
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+    }
+
+    #[test]
+    fn test_list() {
+        let mut repo = test_repo();
+        for name in ["A", "B", "C"] {
+            repo.insert_calendar(&test_calendar(name)).unwrap();
         }
+        let list = |limit, offset| {
+            let mut buf = Vec::new();
+            list(&repo, None, limit, offset, &mut buf).unwrap();
+            serde_json::from_slice::<serde_json::Value>(&buf).unwrap()
+        };
+        for json in [list(None, None), list(Some(1), Some(1))] {
+            assert_eq!(json["total"], 3);
+        }
+        assert_eq!(list(None, None)["calendars"].as_array().unwrap().len(), 3);
+        let page = list(Some(1), Some(1));
+        assert_eq!(page["calendars"].as_array().unwrap().len(), 1);
+        assert_eq!(page["calendars"][0]["name"], "B");
+        assert_eq!(page["calendars"][0]["event_count"], 0);
+    }
+
+    #[test]
+    fn test_export_is_stable() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let start = NaiveDate::from_ymd_opt(2026, 3, 1).unwrap();
+        let events = (0..16)
+            .rev()
+            .map(|day| test_event(cal.id, &format!("Day {day}"), start + Days::new(day)))
+            .collect::<Vec<_>>();
+        repo.insert_events(&events).unwrap();
+        let args = CalendarArgs::try_parse_from(["export", "--id", &cal.id.to_string()]).unwrap();
+        let export = || {
+            let mut buf = Vec::new();
+            export(&repo, &args, &mut buf).unwrap();
+            buf
+        };
+
+        let ics = export();
+        assert_eq!(ics, export());
+        let summaries = String::from_utf8(ics)
+            .unwrap()
+            .lines()
+            .filter_map(|line| line.strip_prefix("SUMMARY:").map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            (0..16).map(|day| format!("Day {day}")).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_collector_order() {
+        let date = |day| NaiveDate::from_ymd_opt(2026, 3, day).unwrap();
+        let cal = test_calendar("Test");
+        let event = |summary: &str, day, rrule: Option<&str>| Event {
+            rrule: rrule.map(|rrule| test_rrule(rrule, date(day))),
+            ..test_event(cal.id, summary, date(day))
+        };
+        let weekly = event("Weekly", 1, Some("FREQ=WEEKLY"));
+        let exception = |original_day, new_day: Option<u32>, new_summary: &str| EventException {
+            id: Uuid::now_v7(),
+            event_id: weekly.id,
+            original_date: date(original_day),
+            new_date: new_day.map(date),
+            new_summary: Some(new_summary.to_string()),
+            new_description: None,
+            range: None,
+        };
+
+        let mut collector = EventCollector::new(cal.clone());
+        collector.process_event(event("Third", 3, None));
+        collector.process_event(weekly.clone());
+        collector.process_event(event("Second", 2, None));
+        collector.process_exception(exception(15, Some(16), "Moved"));
+        collector.process_exception(exception(8, None, "Renamed"));
+        collector.process_event(event(" ", 4, None));
+        collector.process_exception(exception(22, Some(23), " "));
+        let events = collector.finalize().events;
+        // The blank exception keeps the occurrence on the 22nd
+        assert_eq!(events[0].exdates, [date(15), date(8)]);
+        let summaries = events
+            .into_iter()
+            .map(|event| (event.summary, event.date))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            [
+                ("Weekly".to_string(), date(1)),
+                ("Second".to_string(), date(2)),
+                ("Third".to_string(), date(3)),
+                ("Renamed".to_string(), date(8)),
+                ("Moved".to_string(), date(16)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_jsonl() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let today = Utc::now().date_naive();
+        let events = ["One", "Two", "Three"].map(|summary| test_event(cal.id, summary, today));
+        repo.insert_events(&events).unwrap();
+        let id = cal.id.to_string();
+        let args =
+            CalendarArgs::try_parse_from(["export", "--id", &id, "--id", &id, "--format", "jsonl"])
+                .unwrap();
+        let mut buf = Vec::new();
+        export(&repo, &args, &mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), events.len());
+        assert_eq!(lines[0][0]["summary"], "One");
+        assert_eq!(lines[0][1], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_export_drop_older_than() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let today = Utc::now().date_naive();
+        let events = [("Old", 400), ("Last month", 20), ("Today", 0)]
+            .map(|(summary, age)| test_event(cal.id, summary, today - Days::new(age)));
+        repo.insert_events(&events).unwrap();
+        let id = cal.id.to_string();
+        let export = |age| {
+            let mut buf = Vec::new();
+            let args =
+                CalendarArgs::try_parse_from(["export", "--id", &id, "--drop-older-than", age])
+                    .unwrap();
+            export(&repo, &args, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let ics = export("30d");
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(!ics.contains("SUMMARY:Old\r\n"));
+        let ics = export("1w");
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("SUMMARY:Today\r\n"));
+        assert_eq!(export("100w").matches("BEGIN:VEVENT").count(), 3);
+    }
+
+    #[test]
+    fn test_export_since_sequence() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let today = Utc::now().date_naive();
+        let events = [("Pushed", 0), ("Changed", 2)].map(|(summary, sequence)| Event {
+            sequence,
+            ..test_event(cal.id, summary, today)
+        });
+        repo.insert_events(&events).unwrap();
+        let id = cal.id.to_string();
+        let export = |args: &[&str]| {
+            let mut buf = Vec::new();
+            let args =
+                CalendarArgs::try_parse_from(["export", "--id", &id].iter().chain(args)).unwrap();
+            export(&repo, &args, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let ics = export(&["--since-sequence", "1"]);
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("\r\nSEQUENCE:2\r\n"), "{ics}");
+        assert!(ics.contains("\r\nSUMMARY:Changed\r\n"), "{ics}");
+        assert_eq!(export(&[]).matches("BEGIN:VEVENT").count(), 2);
+        assert_eq!(export(&["--since-sequence", "2", "--format", "jsonl"]), "");
+    }
+
+    #[test]
+    fn test_export_this_and_future() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let event = |summary: &str| Event {
+            rrule: Some(test_rrule("FREQ=WEEKLY", start)),
+            ..test_event(cal.id, summary, start)
+        };
+        let (moved, ended) = (event("Moved"), event("Ended"));
+        repo.insert_events(&[moved.clone(), ended.clone()]).unwrap();
+        let exception = |event_id, new_date, new_summary: Option<&str>| EventException {
+            id: Uuid::now_v7(),
+            event_id,
+            original_date: NaiveDate::from_ymd_opt(2024, 1, 15).unwrap(),
+            new_date,
+            new_summary: new_summary.map(ToString::to_string),
+            new_description: None,
+            range: Some(RecurrenceRange::ThisAndFuture),
+        };
+        let tuesday = NaiveDate::from_ymd_opt(2024, 1, 16);
+        repo.insert_event_exception(&exception(moved.id, tuesday, Some("Tuesday")))
+            .unwrap();
+        repo.insert_event_exception(&exception(ended.id, None, None))
+            .unwrap();
+
+        let mut buf = Vec::new();
+        let id = cal.id.to_string();
+        let args = CalendarArgs::try_parse_from(["export", "--id", &id]).unwrap();
+        export(&repo, &args, &mut buf).unwrap();
+        let ics = String::from_utf8(buf).unwrap();
+        assert!(ics.contains("\r\nRECURRENCE-ID;RANGE=THISANDFUTURE;VALUE=DATE:20240115\r\n"));
+        assert!(ics.contains("\r\nDTSTART;VALUE=DATE:20240116\r\n"));
+        assert!(!ics.contains("EXDATE"));
+        assert!(ics.contains(";UNTIL=20240114;"), "{ics}");
+    }
+
+    #[test]
+    fn test_after_date_formats() {
+        let id = Uuid::nil().to_string();
+        for value in ["2024-03-01", "01.03.2024", "20240301"] {
+            let args =
+                CalendarArgs::try_parse_from(["export", "--id", &id, "--after", value]).unwrap();
+            assert_eq!(args.after, NaiveDate::from_ymd_opt(2024, 3, 1), "{value}");
+        }
+        assert!(
+            CalendarArgs::try_parse_from(["export", "--id", &id, "--after", "31.02.2024"]).is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("90d"), Ok(Days::new(90)));
+        assert_eq!(parse_age("4w"), Ok(Days::new(28)));
+        assert!(parse_age("4").is_err());
+        assert!(parse_age("-1d").is_err());
+        assert!(parse_age("99999999999999999w").is_err());
+    }
+
+    #[test]
+    fn test_export_prodid() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let export = |prodid| {
+            let mut buf = Vec::new();
+            let id = cal.id.to_string();
+            let prodid = format!("--prodid={prodid}");
+            let args = CalendarArgs::try_parse_from(["export", "--id", &id, &prodid]).unwrap();
+            export(&repo, &args, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert!(export(DEFAULT_PRODID).contains("\r\nPRODID:-//Rizwold//Calendar//NO\r\n"));
+        assert!(export("-//Example//Test//EN").contains("\r\nPRODID:-//Example//Test//EN\r\n"));
+    }
+
+    #[test]
+    fn test_export_calscale() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let id = cal.id.to_string();
+        let export = |extra: &[&str]| {
+            let mut buf = Vec::new();
+            let mut args = vec!["export", "--id", &id];
+            args.extend(extra);
+            let args = CalendarArgs::try_parse_from(args).unwrap();
+            export(&repo, &args, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        assert!(export(&[]).contains("\r\nCALSCALE:GREGORIAN\r\n"));
+        assert!(export(&["--calscale", "julian"]).contains("\r\nCALSCALE:JULIAN\r\n"));
+        assert!(!export(&["--no-calscale"]).contains("CALSCALE"));
+        // Every format exports, JSON lines as nothing without events
+        for format in ExportFormat::value_variants() {
+            let name = format.to_possible_value().unwrap();
+            let output = export(&["--format", name.get_name()]);
+            assert_eq!(output.is_empty(), matches!(format, ExportFormat::Jsonl));
+        }
+        assert!(export(&["--validate"]).contains("\r\nCALSCALE:GREGORIAN\r\n"));
+        assert!(export(&["--explain"]).contains("\n#   CALSCALE:GREGORIAN\n"));
+        assert!(
+            CalendarArgs::try_parse_from(["export", "--id", &id, "--calscale=x", "--no-calscale"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_export_color_and_refresh_interval() {
+        let mut repo = test_repo();
+        let cal = Calendar {
+            color: Some("turquoise".to_string()),
+            refresh_interval: NonZeroU32::new(86400),
+            ..test_calendar("Test")
+        };
+        repo.insert_calendar(&cal).unwrap();
+        let export = |format| {
+            let mut buf = Vec::new();
+            let id = cal.id.to_string();
+            let args =
+                CalendarArgs::try_parse_from(["export", "--id", &id, "--format", format]).unwrap();
+            export(&repo, &args, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+        let ical = export("ical");
+        assert!(ical.contains("\r\nCOLOR:turquoise\r\n"));
+        assert!(ical.contains("\r\nREFRESH-INTERVAL;VALUE=DURATION:P1D\r\n"));
+        let json: serde_json::Value = serde_json::from_str(&export("json")).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0][0]["color"], "turquoise");
+        assert_eq!(json[0][0]["refresh_interval"], 86400);
     }
 }
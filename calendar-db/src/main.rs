@@ -1,6 +1,7 @@
 use std::process::ExitCode;
 
 use clap::Parser as ClapParser;
+use common::logging::{self, LogArgs};
 
 use calendar_db::Commands;
 
@@ -8,22 +9,25 @@ use calendar_db::Commands;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    log: LogArgs,
 }
 
-fn try_main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+fn try_main(cli: Cli) -> anyhow::Result<()> {
     tracing::debug!("Got CLI args: {cli:?}");
     cli.command.run()
 }
 
 fn main() -> ExitCode {
-    match try_main() {
+    let cli = Cli::parse();
+    let _logger_guard = logging::init(cli.log.log_format, "calendar-db.log");
+    match try_main(cli) {
         Ok(()) => {
             tracing::info!("Success");
             ExitCode::SUCCESS
         }
         Err(err) => {
-            tracing::error!("{err}");
+            tracing::error!(error = %err, "Command failed");
             ExitCode::FAILURE
         }
     }
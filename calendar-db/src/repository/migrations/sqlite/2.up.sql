@@ -0,0 +1,3 @@
+-- Valgfrie kalenderegenskaper fra RFC 7986
+ALTER TABLE calendars ADD COLUMN color TEXT;
+ALTER TABLE calendars ADD COLUMN refresh_interval INTEGER CHECK (refresh_interval > 0); -- sekunder
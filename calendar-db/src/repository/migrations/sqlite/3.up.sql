@@ -0,0 +1,2 @@
+-- Unntak som gjelder denne og alle senere forekomster
+ALTER TABLE event_exceptions ADD COLUMN range TEXT CHECK (range IN ('THISANDFUTURE'));
@@ -1,15 +1,55 @@
+use core::fmt;
+
+use chrono::{DateTime, NaiveDate, Utc};
 use uuid::Uuid;
 
 use crate::types::{Calendar, Event, EventException};
 
 pub mod sqlite;
 
+/// Repository failures callers may want to handle specifically.
+///
+/// Returned wrapped in [`anyhow::Error`]; use `downcast_ref` to inspect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RepositoryError {
+    /// A row with the given id already exists.
+    Conflict(Uuid),
+    /// A stored event has an RRULE that cannot be parsed.
+    Corrupt { event_id: Uuid, rrule: String },
+}
+
+impl fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Conflict(id) => write!(f, "Duplicate id {id}"),
+            Self::Corrupt { event_id, rrule } => {
+                write!(f, "Invalid RRULE in event {event_id}: {rrule}")
+            }
+        }
+    }
+}
+
+impl core::error::Error for RepositoryError {}
+
+/// Which events [`Repository::for_each_event`] includes, all by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Only events with an initial start on or after this date. A
+    /// recurring event starting earlier is left out even if it has later
+    /// occurrences.
+    pub after: Option<NaiveDate>,
+    /// Only events with a `SEQUENCE` greater than this.
+    pub since_sequence: Option<u32>,
+}
+
 pub trait Repository {
     /// # Errors
     ///
     /// May return an error if the query fails.
     fn get_calendar(&self, id: Uuid) -> anyhow::Result<Option<Calendar>>;
 
+    /// Calendars oldest first, by `created_at` and then id.
+    ///
     /// # Errors
     ///
     /// May return an error if the query fails.
@@ -17,12 +57,56 @@ pub trait Repository {
     where
         F: FnMut(Calendar) -> anyhow::Result<()>;
 
+    /// Calendars with `last_modified` at or after `since`, oldest first.
+    ///
     /// # Errors
     ///
     /// May return an error if the query fails.
-    fn for_each_event<F>(&self, calendar_id: Option<Uuid>, callback: F) -> anyhow::Result<()>
+    fn calendars_modified_since(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<Calendar>>;
+
+    /// Calendars oldest first, or oldest modification first when only those
+    /// modified at or after `since` are included. The first `offset` are
+    /// skipped and at most `limit` returned.
+    ///
+    /// # Errors
+    ///
+    /// May return an error if the query fails.
+    fn list_calendars(
+        &self,
+        since: Option<DateTime<Utc>>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> anyhow::Result<Vec<Calendar>>;
+
+    /// Number of calendars, counting only those modified at or after
+    /// `since` if given.
+    ///
+    /// # Errors
+    ///
+    /// May return an error if the query fails.
+    fn count_calendars(&self, since: Option<DateTime<Utc>>) -> anyhow::Result<usize>;
+
+    /// Events in `calendar_id`, or in all calendars if `None`, matching
+    /// `filter`, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// May return an error if the query fails.
+    fn for_each_event<F>(
+        &self,
+        calendar_id: Option<Uuid>,
+        filter: EventFilter,
+        callback: F,
+    ) -> anyhow::Result<()>
     where
         F: FnMut(Event) -> anyhow::Result<()>;
+    /// The event with `id`, if any.
+    ///
+    /// # Errors
+    ///
+    /// May return an error if the query fails.
+    fn get_event(&self, id: Uuid) -> anyhow::Result<Option<Event>>;
+
     /// # Errors
     ///
     /// May return an error if the query fails.
@@ -34,6 +118,13 @@ pub trait Repository {
     where
         F: FnMut(EventException) -> anyhow::Result<()>;
 
+    /// Number of events in a calendar.
+    ///
+    /// # Errors
+    ///
+    /// May return an error if the query fails.
+    fn count_events(&self, calendar_id: Uuid) -> anyhow::Result<usize>;
+
     /// # Errors
     /// May return a `RepositoryError` if database communication fails.
     fn has_latest_migrations(&self) -> Result<bool, anyhow::Error>;
@@ -45,4 +136,25 @@ pub trait WritableRepository: Repository {
     ///
     /// May return a `RepositoryError` if the migration fails.
     fn migrate(&mut self) -> Result<(), anyhow::Error>;
+
+    /// # Errors
+    ///
+    /// Returns [`RepositoryError::Conflict`] if a calendar with the same id
+    /// exists, or another error if the query fails.
+    fn insert_calendar(&mut self, calendar: &Calendar) -> anyhow::Result<()>;
+
+    /// Insert all `events` in a single transaction. Nothing is inserted if
+    /// any of them fails.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`RepositoryError::Conflict`] if an event with the same id
+    /// exists, or another error if the query fails.
+    fn insert_events(&mut self, events: &[Event]) -> anyhow::Result<()>;
+
+    /// # Errors
+    ///
+    /// Returns [`RepositoryError::Conflict`] if an exception with the same
+    /// id exists, or another error if the query fails.
+    fn insert_event_exception(&mut self, exception: &EventException) -> anyhow::Result<()>;
 }
@@ -0,0 +1,13 @@
+INSERT INTO events (
+    id,
+    calendar_id,
+    summary,
+    description,
+    url,
+    dtstart_initial,
+    duration_days,
+    rrule,
+    sequence,
+    created_at,
+    last_modified
+) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
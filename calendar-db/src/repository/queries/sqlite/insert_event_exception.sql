@@ -0,0 +1,9 @@
+INSERT INTO event_exceptions (
+    id,
+    event_id,
+    original_date,
+    new_date,
+    new_summary,
+    new_description,
+    range
+) VALUES (?, ?, ?, ?, ?, ?, ?)
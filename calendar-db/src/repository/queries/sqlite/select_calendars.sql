@@ -0,0 +1,9 @@
+SELECT
+    id,
+    name,
+    description,
+    created_at,
+    last_modified,
+    color,
+    refresh_interval
+FROM calendars
@@ -4,5 +4,6 @@ SELECT
     original_date,
     new_date,
     NULLIF(TRIM(new_summary), '') AS new_summary,
-    NULLIF(TRIM(new_description), '') AS new_description
+    NULLIF(TRIM(new_description), '') AS new_description,
+    range
 FROM event_exceptions
@@ -1,24 +1,39 @@
 use core::result::Result;
 use std::path::Path;
 
+use ::calendar::{RRULE_TIMEZONE, start_of_day};
 use anyhow::Context as _;
-use chrono::{DateTime, NaiveDate};
-use rusqlite::{Connection, OpenFlags, OptionalExtension as _, TransactionBehavior};
+use chrono::{DateTime, NaiveDate, Utc};
+use rusqlite::{
+    Connection, ErrorCode, OpenFlags, OptionalExtension as _, TransactionBehavior, ffi,
+};
 use tracing::error;
 use uuid::Uuid;
 
 use crate::types::{Calendar, Event, EventException};
 
-use super::{Repository, WritableRepository};
+use super::{EventFilter, Repository, RepositoryError, WritableRepository};
 
 #[derive(Debug)]
 pub(crate) struct Sqlite3Repo {
     conn: Connection,
+    strict: bool,
 }
 
 impl Sqlite3Repo {
     pub(crate) const fn new(conn: rusqlite::Connection) -> Self {
-        Self { conn }
+        Self {
+            conn,
+            strict: false,
+        }
+    }
+
+    /// Fail with [`RepositoryError::Corrupt`] on invalid stored data instead
+    /// of logging and skipping it.
+    #[must_use]
+    pub(crate) const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
     }
 
     /// # Errors
@@ -32,9 +47,91 @@ impl Sqlite3Repo {
         let conn = Connection::open_with_flags(path, flags.unwrap_or_default())?;
         Ok(Self::new(conn))
     }
+
+    /// The event read by [`event_from_row`], or
+    /// [`RepositoryError::Corrupt`] if its RRULE is invalid in strict mode.
+    fn checked_event(
+        &self,
+        (event, invalid_rrule): (Event, Option<String>),
+    ) -> anyhow::Result<Event> {
+        match invalid_rrule {
+            Some(rrule) if self.strict => Err(RepositoryError::Corrupt {
+                event_id: event.id,
+                rrule,
+            }
+            .into()),
+            _ => Ok(event),
+        }
+    }
+}
+
+fn calendar_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Calendar> {
+    let id_str: String = row.get(0)?;
+    let id = uuid::Uuid::parse_str(&id_str)
+        .with_context(|| "Kunne ikke hente kolonne 0")
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?;
+    Ok(Calendar {
+        id,
+        name: row.get(1)?,
+        description: row.get(2)?,
+        created_at: row.get(3)?,
+        last_modified: row.get(4)?,
+        color: row.get(5)?,
+        refresh_interval: row.get(6)?,
+    })
+}
+
+/// Map a row from `select_events.sql` to an event and, if it could not be
+/// parsed, the stored RRULE.
+fn event_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<(Event, Option<String>)> {
+    let str_val: String = row.get(0)?;
+    let id = uuid::Uuid::parse_str(&str_val)
+        .with_context(|| "Kunne ikke hente kolonne 0")
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?;
+    let str_val: String = row.get(1)?;
+    let calendar_id = uuid::Uuid::parse_str(&str_val)
+        // .with_context(|| "Kunne ikke hente kolonne 1")
+        .map_err(|e| {
+            rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, e.into())
+        })?;
+    let dtstart_initial: NaiveDate = row.get(5)?;
+    let rrule_dtstart = start_of_day(dtstart_initial, RRULE_TIMEZONE);
+
+    let str_val: Option<String> = row.get(7)?;
+    let (rrule, invalid_rrule) = str_val
+        .map(|str_val| str_val.trim().to_string())
+        .filter(|str_val| !str_val.is_empty())
+        .map_or((None, None), |str_val| {
+            match parse_rrule(&str_val, rrule_dtstart) {
+                Ok(rrule) => (Some(rrule), None),
+                Err(err) => {
+                    error!(event = %id, "Unable to read RRULE {err}");
+                    (None, Some(str_val))
+                }
+            }
+        });
+    let event = Event {
+        id,
+        calendar_id,
+        summary: row.get(2)?,
+        description: row.get(3)?,
+        url: row.get(4)?,
+        dtstart_initial,
+        duration_days: row.get(6)?,
+        rrule,
+        sequence: row.get(8)?,
+        created_at: row.get(9)?,
+        last_modified: row.get(10)?,
+    };
+    Ok((event, invalid_rrule))
 }
 
 impl Repository for Sqlite3Repo {
+    #[allow(clippy::needless_type_cast)]
     fn has_latest_migrations(&self) -> Result<bool, anyhow::Error> {
         let migrations = migrations();
         let user_version: u32 =
@@ -47,46 +144,31 @@ impl Repository for Sqlite3Repo {
 
     fn get_calendar(&self, id: uuid::Uuid) -> anyhow::Result<Option<Calendar>> {
         let query =
-            "SELECT id, name, description, created_at, last_modified FROM calendars WHERE id = ?";
+            include_str!("queries/sqlite/select_calendars.sql").to_string() + " WHERE id = ?";
         Ok(self
             .conn
-            .query_row(query, rusqlite::params![id.to_string()], |row| {
-                Ok(Calendar {
-                    id,
-                    name: row.get(1)?,
-                    description: row.get(2)?,
-                    created_at: row.get(3)?,
-                    last_modified: row.get(4)?,
-                })
-            })
+            .query_row(&query, rusqlite::params![id.to_string()], calendar_from_row)
             .optional()?)
     }
 
+    fn count_events(&self, calendar_id: Uuid) -> anyhow::Result<usize> {
+        let query = "SELECT COUNT(*) FROM events WHERE calendar_id = ?";
+        let count: i64 =
+            self.conn
+                .query_row(query, rusqlite::params![calendar_id.to_string()], |row| {
+                    row.get(0)
+                })?;
+        Ok(usize::try_from(count)?)
+    }
+
     fn for_each_calendar<F>(&self, mut callback: F) -> anyhow::Result<()>
     where
         F: FnMut(Calendar) -> anyhow::Result<()>,
     {
-        let query = "SELECT id, name, description, created_at, last_modified FROM calendars";
-        let mut stmt = self.conn.prepare(query)?;
-        let rows = stmt.query_map([], |row| {
-            let id_str: String = row.get(0)?;
-            let id = uuid::Uuid::parse_str(&id_str)
-                .with_context(|| "Kunne ikke hente kolonne 0")
-                .map_err(|e| {
-                    rusqlite::Error::FromSqlConversionFailure(
-                        0,
-                        rusqlite::types::Type::Text,
-                        e.into(),
-                    )
-                })?;
-            Ok(Calendar {
-                id,
-                name: row.get(1)?,
-                description: row.get(2)?,
-                created_at: row.get(3)?,
-                last_modified: row.get(4)?,
-            })
-        })?;
+        let query = include_str!("queries/sqlite/select_calendars.sql").to_string()
+            + " ORDER BY created_at, id";
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map([], calendar_from_row)?;
         for row in rows {
             match row {
                 Ok(row) => callback(row)?,
@@ -98,88 +180,72 @@ impl Repository for Sqlite3Repo {
         Ok(())
     }
 
-    fn for_each_event<F>(&self, calendar_id: Option<Uuid>, mut callback: F) -> anyhow::Result<()>
+    fn calendars_modified_since(&self, since: DateTime<Utc>) -> anyhow::Result<Vec<Calendar>> {
+        self.list_calendars(Some(since), None, 0)
+    }
+
+    fn list_calendars(
+        &self,
+        since: Option<DateTime<Utc>>,
+        limit: Option<u32>,
+        offset: u32,
+    ) -> anyhow::Result<Vec<Calendar>> {
+        let mut query = include_str!("queries/sqlite/select_calendars.sql").to_string();
+        query += if since.is_some() {
+            " WHERE last_modified >= ?1 ORDER BY last_modified, id"
+        } else {
+            " WHERE ?1 IS NULL ORDER BY created_at, id"
+        };
+        // A negative LIMIT means no limit in SQLite
+        query += " LIMIT ?2 OFFSET ?3";
+        let limit = limit.map_or(-1, i64::from);
+        let mut stmt = self.conn.prepare(&query)?;
+        let rows = stmt.query_map(rusqlite::params![since, limit, offset], calendar_from_row)?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    fn count_calendars(&self, since: Option<DateTime<Utc>>) -> anyhow::Result<usize> {
+        let query = "SELECT COUNT(*) FROM calendars WHERE ?1 IS NULL OR last_modified >= ?1";
+        let count: i64 = self
+            .conn
+            .query_row(query, rusqlite::params![since], |row| row.get(0))?;
+        Ok(usize::try_from(count)?)
+    }
+
+    fn for_each_event<F>(
+        &self,
+        calendar_id: Option<Uuid>,
+        filter: EventFilter,
+        mut callback: F,
+    ) -> anyhow::Result<()>
     where
         F: FnMut(Event) -> anyhow::Result<()>,
     {
         let mut query = include_str!("queries/sqlite/select_events.sql").to_string();
-        #[allow(clippy::option_if_let_else)]
-        let params = match calendar_id {
-            Some(id) => {
-                query += " WHERE calendar_id = ?";
-                rusqlite::params![id.to_string()]
-            }
-            None => rusqlite::params![],
-        };
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+        if let Some(id) = calendar_id {
+            conditions.push("calendar_id = ?");
+            params.push(id.to_string());
+        }
+        if let Some(after) = filter.after {
+            conditions.push("dtstart_initial >= ?");
+            params.push(after.to_string());
+        }
+        if let Some(sequence) = filter.since_sequence {
+            conditions.push("sequence > ?");
+            params.push(sequence.to_string());
+        }
+        if !conditions.is_empty() {
+            query += " WHERE ";
+            query += &conditions.join(" AND ");
+        }
+        query += " ORDER BY created_at, id";
         let mut stmt = self.conn.prepare(&query)?;
-        let rows = stmt.query_map(params, |row| {
-            let str_val: String = row.get(0)?;
-            let id = uuid::Uuid::parse_str(&str_val)
-                .with_context(|| "Kunne ikke hente kolonne 0")
-                .map_err(|e| {
-                    rusqlite::Error::FromSqlConversionFailure(
-                        0,
-                        rusqlite::types::Type::Text,
-                        e.into(),
-                    )
-                })?;
-            let str_val: String = row.get(1)?;
-            let calendar_id = uuid::Uuid::parse_str(&str_val)
-                // .with_context(|| "Kunne ikke hente kolonne 1")
-                .map_err(|e| {
-                    rusqlite::Error::FromSqlConversionFailure(
-                        0,
-                        rusqlite::types::Type::Text,
-                        e.into(),
-                    )
-                })?;
-            let dtstart_initial: NaiveDate = row.get(5)?;
-            let naive_datetime = dtstart_initial
-                .and_hms_opt(0, 0, 0)
-                .expect("This should not happen");
-            let rrule_dtstart: DateTime<rrule::Tz> =
-                naive_datetime.and_local_timezone(rrule::Tz::LOCAL).unwrap();
-
-            let str_val: Option<String> = row.get(7)?;
-            let rrule = str_val.map_or_else(
-                || None,
-                |str_val| {
-                    let str_val = str_val.trim();
-                    if str_val.is_empty() {
-                        return None;
-                    }
-                    match str_val.parse::<rrule::RRule<rrule::Unvalidated>>() {
-                        Ok(x) => match x.validate(rrule_dtstart) {
-                            Ok(x) => Some(x),
-                            Err(err) => {
-                                error!("Unable to read RRULE {err}");
-                                None
-                            }
-                        },
-                        Err(err) => {
-                            error!("Unable to read RRULE {err}");
-                            None
-                        }
-                    }
-                },
-            );
-            Ok(Event {
-                id,
-                calendar_id,
-                summary: row.get(2)?,
-                description: row.get(3)?,
-                url: row.get(4)?,
-                dtstart_initial,
-                duration_days: row.get(6)?,
-                rrule,
-                sequence: row.get(8)?,
-                created_at: row.get(9)?,
-                last_modified: row.get(10)?,
-            })
-        })?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), event_from_row)?;
         for row in rows {
             match row {
-                Ok(row) => callback(row)?,
+                Ok(row) => callback(self.checked_event(row)?)?,
                 Err(err) => {
                     error!("Failed to get calendar: {err}");
                 }
@@ -188,6 +254,15 @@ impl Repository for Sqlite3Repo {
         Ok(())
     }
 
+    fn get_event(&self, id: Uuid) -> anyhow::Result<Option<Event>> {
+        let query = include_str!("queries/sqlite/select_events.sql").to_string() + " WHERE id = ?";
+        self.conn
+            .query_row(&query, [id.to_string()], event_from_row)
+            .optional()?
+            .map(|row| self.checked_event(row))
+            .transpose()
+    }
+
     fn for_each_event_exceptions<F>(
         &self,
         event_id: Option<Uuid>,
@@ -235,6 +310,7 @@ impl Repository for Sqlite3Repo {
                 new_date: row.get(3)?,
                 new_summary: row.get(4)?,
                 new_description: row.get(5)?,
+                range: row.get(6)?,
             })
         })?;
         for row in rows {
@@ -249,12 +325,26 @@ impl Repository for Sqlite3Repo {
     }
 }
 
+fn parse_rrule(
+    value: &str,
+    dt_start: DateTime<rrule::Tz>,
+) -> Result<rrule::RRule, rrule::RRuleError> {
+    value
+        .parse::<rrule::RRule<rrule::Unvalidated>>()?
+        .validate(dt_start)
+}
+
 #[inline]
-const fn migrations() -> [&'static str; 1] {
-    [include_str!("migrations/sqlite/1.up.sql")]
+const fn migrations() -> [&'static str; 3] {
+    [
+        include_str!("migrations/sqlite/1.up.sql"),
+        include_str!("migrations/sqlite/2.up.sql"),
+        include_str!("migrations/sqlite/3.up.sql"),
+    ]
 }
 
 impl WritableRepository for Sqlite3Repo {
+    #[allow(clippy::needless_type_cast)]
     fn migrate(&mut self) -> Result<(), anyhow::Error> {
         // EXCLUSIVE ensures that it starts with an exclusive write lock. No other
         // readers will be allowed. This generally shouldn't be needed if there is
@@ -277,14 +367,98 @@ impl WritableRepository for Sqlite3Repo {
         tx.commit()?;
         Ok(())
     }
+
+    fn insert_calendar(&mut self, calendar: &Calendar) -> anyhow::Result<()> {
+        let query = "INSERT INTO calendars (id, name, description, created_at, last_modified, color, refresh_interval) VALUES (?, ?, ?, ?, ?, ?, ?)";
+        let result = self.conn.execute(
+            query,
+            rusqlite::params![
+                calendar.id.to_string(),
+                calendar.name,
+                calendar.description.as_deref().unwrap_or_default(),
+                calendar.created_at,
+                calendar.last_modified,
+                calendar.color,
+                calendar.refresh_interval,
+            ],
+        );
+        result
+            .map(|_| ())
+            .map_err(|err| conflict_error(err, calendar.id))
+    }
+
+    fn insert_events(&mut self, events: &[Event]) -> anyhow::Result<()> {
+        let tx = self
+            .conn
+            .transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        {
+            let mut stmt = tx.prepare(include_str!("queries/sqlite/insert_event.sql"))?;
+            for event in events {
+                stmt.execute(rusqlite::params![
+                    event.id.to_string(),
+                    event.calendar_id.to_string(),
+                    event.summary,
+                    event.description.as_deref().unwrap_or_default(),
+                    event.url,
+                    event.dtstart_initial,
+                    event.duration_days.get(),
+                    event.rrule.as_ref().map(ToString::to_string),
+                    event.sequence,
+                    event.created_at,
+                    event.last_modified,
+                ])
+                .map_err(|err| conflict_error(err, event.id))?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn insert_event_exception(&mut self, exception: &EventException) -> anyhow::Result<()> {
+        let query = include_str!("queries/sqlite/insert_event_exception.sql");
+        let result = self.conn.execute(
+            query,
+            rusqlite::params![
+                exception.id.to_string(),
+                exception.event_id.to_string(),
+                exception.original_date,
+                exception.new_date,
+                exception.new_summary,
+                exception.new_description,
+                exception.range,
+            ],
+        );
+        result
+            .map(|_| ())
+            .map_err(|err| conflict_error(err, exception.id))
+    }
 }
 
+/// Map a primary key violation to [`RepositoryError::Conflict`].
+fn conflict_error(err: rusqlite::Error, id: Uuid) -> anyhow::Error {
+    match err {
+        rusqlite::Error::SqliteFailure(err, _)
+            if err.code == ErrorCode::ConstraintViolation
+                && err.extended_code == ffi::SQLITE_CONSTRAINT_PRIMARYKEY =>
+        {
+            RepositoryError::Conflict(id).into()
+        }
+        err => err.into(),
+    }
+}
+
+/// With `strict`, events with an invalid RRULE fail with
+/// [`RepositoryError::Corrupt`] instead of being read without recurrence.
+///
 /// # Errors
 ///
 /// Will return `Err` if `path` cannot be converted to a C-compatible
 /// string or if the underlying SQLite open call fails.
-pub fn open_readonly_repository<P: AsRef<Path>>(path: P) -> Result<impl Repository, anyhow::Error> {
-    Sqlite3Repo::open(path, Some(OpenFlags::SQLITE_OPEN_READ_ONLY))
+pub fn open_readonly_repository<P: AsRef<Path>>(
+    path: P,
+    strict: bool,
+) -> Result<impl Repository, anyhow::Error> {
+    Ok(Sqlite3Repo::open(path, Some(OpenFlags::SQLITE_OPEN_READ_ONLY))?.strict(strict))
 }
 
 /// # Errors
@@ -305,16 +479,244 @@ pub fn open_writable_in_memory_repository() -> Result<impl WritableRepository, a
     Ok(Sqlite3Repo::new(rusqlite::Connection::open_in_memory()?))
 }
 
-// #[cfg(test)]
-// mod test {
-//     use rusqlite::Connection;
-//
-//     use super::Sqlite3Repo;
-//     use crate::repository::WritableRepository;
-//
-//     fn repo() -> Sqlite3Repo {
-//         let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap());
-//         repo.migrate().unwrap();
-//         repo
-//     }
-// }
+#[cfg(test)]
+mod test {
+    use core::num::NonZeroU32;
+
+    use chrono::{Days, NaiveDate, TimeDelta, Utc};
+    use rusqlite::Connection;
+
+    use super::{Sqlite3Repo, migrations};
+    use crate::{
+        repository::{EventFilter, Repository, RepositoryError, WritableRepository},
+        test_calendar, test_event, test_repo,
+        types::{Calendar, Event},
+    };
+
+    fn insert_event(repo: &Sqlite3Repo, calendar_id: uuid::Uuid, date: &str) -> uuid::Uuid {
+        let id = uuid::Uuid::now_v7();
+        repo.conn
+            .execute(
+                "INSERT INTO events (id, calendar_id, summary, dtstart_initial) VALUES (?, ?, ?, ?)",
+                rusqlite::params![id.to_string(), calendar_id.to_string(), "Event", date],
+            )
+            .unwrap();
+        id
+    }
+
+    #[test]
+    fn test_insert_calendar_conflict() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let err = repo.insert_calendar(&cal).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<RepositoryError>(),
+            Some(&RepositoryError::Conflict(cal.id))
+        );
+    }
+
+    #[test]
+    fn test_calendars_modified_since() {
+        let mut repo = test_repo();
+        let now = Utc::now();
+        let old = Calendar {
+            last_modified: now - TimeDelta::days(2),
+            ..test_calendar("Old")
+        };
+        let new = test_calendar("New");
+        repo.insert_calendar(&old).unwrap();
+        repo.insert_calendar(&new).unwrap();
+        let since = now - TimeDelta::days(1);
+        let calendars = repo.calendars_modified_since(since).unwrap();
+        assert_eq!(calendars.iter().map(|c| c.id).collect::<Vec<_>>(), [new.id]);
+    }
+
+    #[test]
+    fn test_for_each_calendar_order() {
+        let mut repo = test_repo();
+        let now = Utc::now();
+        let calendars: Vec<_> = [2, 0, 1]
+            .into_iter()
+            .map(|days| Calendar {
+                created_at: now - TimeDelta::days(days),
+                ..test_calendar(&format!("Created {days} days ago"))
+            })
+            .collect();
+        for cal in &calendars {
+            repo.insert_calendar(cal).unwrap();
+        }
+        let mut names = Vec::new();
+        repo.for_each_calendar(|cal| {
+            names.push(cal.name);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            names,
+            [
+                "Created 2 days ago",
+                "Created 1 days ago",
+                "Created 0 days ago"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_list_calendars_page() {
+        let mut repo = test_repo();
+        let mut ids: Vec<_> = (0..5)
+            .map(|i| {
+                let cal = test_calendar(&format!("Calendar {i}"));
+                repo.insert_calendar(&cal).unwrap();
+                cal.id
+            })
+            .collect();
+        ids.sort();
+        let page = repo.list_calendars(None, Some(2), 2).unwrap();
+        assert_eq!(page.iter().map(|c| c.id).collect::<Vec<_>>(), ids[2..4]);
+        assert_eq!(repo.list_calendars(None, None, 3).unwrap().len(), 2);
+        assert_eq!(repo.count_calendars(None).unwrap(), 5);
+        let future = Utc::now() + TimeDelta::days(1);
+        assert_eq!(repo.count_calendars(Some(future)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_migrate_keeps_calendars() {
+        let mut repo = Sqlite3Repo::new(Connection::open_in_memory().unwrap());
+        repo.conn.execute_batch(migrations()[0]).unwrap();
+        repo.conn.pragma_update(None, "user_version", 1).unwrap();
+        let cal = test_calendar("Old");
+        repo.conn
+            .execute(
+                "INSERT INTO calendars (id, name) VALUES (?, ?)",
+                rusqlite::params![cal.id.to_string(), cal.name],
+            )
+            .unwrap();
+        assert!(!repo.has_latest_migrations().unwrap());
+        repo.migrate().unwrap();
+        assert!(repo.has_latest_migrations().unwrap());
+        let found = repo.get_calendar(cal.id).unwrap().unwrap();
+        assert_eq!(found.name, "Old");
+        assert_eq!(found.color, None);
+        assert_eq!(found.refresh_interval, None);
+    }
+
+    #[test]
+    fn test_calendar_round_trip() {
+        let mut repo = test_repo();
+        let cal = Calendar {
+            color: Some("turquoise".to_string()),
+            refresh_interval: NonZeroU32::new(3600),
+            ..test_calendar("Test")
+        };
+        repo.insert_calendar(&cal).unwrap();
+        let found = repo.get_calendar(cal.id).unwrap().unwrap();
+        assert_eq!(found.color, cal.color);
+        assert_eq!(found.refresh_interval, cal.refresh_interval);
+    }
+
+    #[test]
+    fn test_count_events() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        let other = test_calendar("Other");
+        repo.insert_calendar(&cal).unwrap();
+        repo.insert_calendar(&other).unwrap();
+        for date in ["2024-03-01", "2024-03-02", "2024-03-03"] {
+            insert_event(&repo, cal.id, date);
+        }
+        insert_event(&repo, other.id, "2024-03-01");
+        assert_eq!(repo.count_events(cal.id).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_insert_events() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let events: Vec<Event> = (0..1000)
+            .map(|n| test_event(cal.id, &format!("Event {n}"), start + Days::new(n)))
+            .collect();
+        repo.insert_events(&events).unwrap();
+        assert_eq!(repo.count_events(cal.id).unwrap(), 1000);
+
+        let err = repo.insert_events(&events[999..]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RepositoryError::Conflict(events[999].id))
+        );
+        assert_eq!(repo.count_events(cal.id).unwrap(), 1000);
+    }
+
+    #[test]
+    fn test_get_event() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let id = insert_event(&repo, cal.id, "2024-03-01");
+        let event = repo.get_event(id).unwrap().unwrap();
+        assert_eq!(event.id, id);
+        assert_eq!(event.calendar_id, cal.id);
+        assert_eq!(event.dtstart_initial.to_string(), "2024-03-01");
+        assert!(repo.get_event(uuid::Uuid::now_v7()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_events_after() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        for date in ["2024-02-29", "2024-03-01", "2024-03-02"] {
+            insert_event(&repo, cal.id, date);
+        }
+        let mut dates = Vec::new();
+        let filter = EventFilter {
+            after: NaiveDate::from_ymd_opt(2024, 3, 1),
+            ..EventFilter::default()
+        };
+        repo.for_each_event(Some(cal.id), filter, |evt| {
+            dates.push(evt.dtstart_initial.to_string());
+            Ok(())
+        })
+        .unwrap();
+        dates.sort();
+        assert_eq!(dates, ["2024-03-01", "2024-03-02"]);
+    }
+
+    #[test]
+    fn test_invalid_rrule() {
+        let mut repo = test_repo();
+        let cal = test_calendar("Test");
+        repo.insert_calendar(&cal).unwrap();
+        let id = insert_event(&repo, cal.id, "2024-03-01");
+        repo.conn
+            .execute(
+                "UPDATE events SET rrule = 'FREQ=SOMETIMES' WHERE id = ?",
+                [id.to_string()],
+            )
+            .unwrap();
+
+        let mut events = Vec::new();
+        repo.for_each_event(Some(cal.id), EventFilter::default(), |evt| {
+            events.push(evt);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].rrule.is_none());
+
+        let repo = repo.strict(true);
+        let err = repo
+            .for_each_event(Some(cal.id), EventFilter::default(), |_| Ok(()))
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref(),
+            Some(&RepositoryError::Corrupt {
+                event_id: id,
+                rrule: "FREQ=SOMETIMES".to_string(),
+            })
+        );
+    }
+}
@@ -1,4 +1,7 @@
-use core::{fmt, num::NonZeroU8};
+use core::{
+    fmt,
+    num::{NonZeroU8, NonZeroU32},
+};
 
 use chrono::{DateTime, NaiveDate, Utc};
 use rusqlite::{
@@ -14,6 +17,10 @@ pub struct Calendar {
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
     pub last_modified: DateTime<Utc>,
+    /// CSS3 color name
+    pub color: Option<String>,
+    /// Suggested polling interval for subscribers, in seconds
+    pub refresh_interval: Option<NonZeroU32>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -39,28 +46,75 @@ pub struct EventException {
     pub new_date: Option<NaiveDate>,
     pub new_summary: Option<String>,
     pub new_description: Option<String>,
+    /// Set if the exception also applies to all following occurrences.
+    pub range: Option<RecurrenceRange>,
+}
+
+/// The `RANGE` of an exception, only `THISANDFUTURE` is defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum RecurrenceRange {
+    #[serde(rename = "THISANDFUTURE")]
+    ThisAndFuture,
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct InvalidUrl;
+impl FromSql for RecurrenceRange {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "THISANDFUTURE" => Ok(Self::ThisAndFuture),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for RecurrenceRange {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            Self::ThisAndFuture => Ok(ToSqlOutput::from("THISANDFUTURE")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidUrl {
+    /// The value could not be parsed as a URL.
+    Parse(url::ParseError),
+    /// The scheme is neither `http` nor `https`.
+    Scheme,
+    /// The URL contains a username or password.
+    Credentials,
+    /// The database value could not be read as text.
+    Sql,
+}
 
 impl fmt::Display for InvalidUrl {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "Invalid short URL")
+        match self {
+            Self::Parse(err) => write!(f, "Invalid short URL: {err}"),
+            Self::Scheme => write!(f, "Invalid short URL: scheme must be http or https"),
+            Self::Credentials => write!(f, "Invalid short URL: credentials are not allowed"),
+            Self::Sql => write!(f, "Invalid short URL"),
+        }
     }
 }
 
-impl core::error::Error for InvalidUrl {}
+impl core::error::Error for InvalidUrl {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Parse(err) => Some(err),
+            Self::Scheme | Self::Credentials | Self::Sql => None,
+        }
+    }
+}
 
 impl From<FromSqlError> for InvalidUrl {
     fn from(_: FromSqlError) -> Self {
-        Self
+        Self::Sql
     }
 }
 
 impl From<url::ParseError> for InvalidUrl {
-    fn from(_: url::ParseError) -> Self {
-        Self
+    fn from(err: url::ParseError) -> Self {
+        Self::Parse(err)
     }
 }
 
@@ -102,10 +156,12 @@ impl TryFrom<&str> for Url {
 
     fn try_from(s: &str) -> Result<Self, Self::Error> {
         let url = url::Url::parse(s)?;
-        if is_http_or_https(&url) && !has_password(&url) && !has_username(&url) {
-            Ok(Self(url))
+        if !is_http_or_https(&url) {
+            Err(InvalidUrl::Scheme)
+        } else if has_password(&url) || has_username(&url) {
+            Err(InvalidUrl::Credentials)
         } else {
-            Err(InvalidUrl)
+            Ok(Self(url))
         }
     }
 }
@@ -169,6 +225,8 @@ impl ToSql for UnixTimestamp {
 
 #[cfg(test)]
 mod test {
+    use core::error::Error as _;
+
     use super::*;
 
     #[test]
@@ -179,8 +237,16 @@ mod test {
 
     #[test]
     fn test_url_try_from_invalid_scheme() {
-        let result = Url::try_from("ftp://localhost/");
-        assert!(result.is_err());
+        let err = Url::try_from("ftp://localhost/").unwrap_err();
+        assert_eq!(err, InvalidUrl::Scheme);
+        assert!(err.to_string().contains("scheme"));
+    }
+
+    #[test]
+    fn test_url_try_from_malformed() {
+        let err = Url::try_from("localhost").unwrap_err();
+        assert!(err.source().is_some());
+        assert!(err.to_string().contains("relative URL without a base"));
     }
 
     #[test]
@@ -0,0 +1,160 @@
+//! Reminders before events, written as `VALARM`.
+
+use core::fmt;
+
+use chrono::Duration;
+use ics::properties::{Description, Trigger};
+
+use crate::{RenderOptions, format_duration};
+
+/// What an [`Alarm`] does when it triggers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum AlarmAction {
+    /// Show a message
+    #[default]
+    Display,
+    /// Play a sound
+    Audio,
+}
+
+/// Writes the name used on the command line.
+impl fmt::Display for AlarmAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Display => "display",
+            Self::Audio => "audio",
+        })
+    }
+}
+
+/// A reminder some time before the start of an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Alarm {
+    /// How long before the start of the event the alarm triggers.
+    pub before: Duration,
+    pub action: AlarmAction,
+    /// Message of a display alarm. Defaults to the event summary followed
+    /// by [`Alarm::when`].
+    pub description: Option<String>,
+}
+
+impl Alarm {
+    #[must_use]
+    pub const fn new(before: Duration, action: AlarmAction) -> Self {
+        Self {
+            before,
+            action,
+            description: None,
+        }
+    }
+
+    /// When the event is, seen from the time the alarm triggers, in
+    /// Norwegian: `i dag`, `i morgen` or `om 3 dager`.
+    #[must_use]
+    pub fn when(&self) -> String {
+        const DAY: i64 = 86_400;
+        let days = (self.before.num_seconds().max(0) + DAY - 1) / DAY;
+        match days {
+            0 => "i dag".to_string(),
+            1 => "i morgen".to_string(),
+            days => format!("om {days} dager"),
+        }
+    }
+
+    /// The `VALARM` for an event with `summary`.
+    pub(crate) fn to_ics(&self, summary: &str, options: RenderOptions) -> ics::Alarm<'_> {
+        let trigger = if self.before > Duration::zero() {
+            format!("-{}", format_duration(self.before))
+        } else {
+            format_duration(self.before)
+        };
+        let trigger = Trigger::new(trigger);
+        match self.action {
+            AlarmAction::Display => {
+                let description = self
+                    .description
+                    .clone()
+                    .unwrap_or_else(|| format!("{summary} {}", self.when()));
+                ics::Alarm::display(
+                    trigger,
+                    Description::new(ics::escape_text(options.text(&description).into_owned())),
+                )
+            }
+            AlarmAction::Audio => ics::Alarm::audio(trigger),
+        }
+    }
+}
+
+/// Parse how long before an event to remind, as a number followed by
+/// `d`, `h` or `m`, such as `1d` or `90m`.
+///
+/// Intended as a `value_parser` for command line reminder arguments.
+///
+/// # Errors
+///
+/// Returns a message if `value` is not a number and a unit.
+pub fn parse_reminder(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let error = || format!("Invalid reminder '{value}', expected a number and d, h or m");
+    let split = value.len().checked_sub(1).ok_or_else(error)?;
+    let (number, unit) = value.split_at_checked(split).ok_or_else(error)?;
+    let number: u32 = number.parse().map_err(|_| error())?;
+    let number = i64::from(number);
+    match unit {
+        "d" | "D" => Ok(Duration::days(number)),
+        "h" | "H" => Ok(Duration::hours(number)),
+        "m" | "M" => Ok(Duration::minutes(number)),
+        _ => Err(error()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(alarm: &Alarm) -> String {
+        alarm
+            .to_ics("🗑️ Restavfall", RenderOptions::default())
+            .to_string()
+    }
+
+    #[test]
+    fn test_render() {
+        let mut alarm = Alarm::new(Duration::days(1), AlarmAction::Display);
+        assert_eq!(
+            render(&alarm),
+            "BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-P1D\r\nDESCRIPTION:🗑️ Restavfall i morgen\r\nEND:VALARM\r\n"
+        );
+        alarm.description = Some("Sett ut dunken; nå".to_string());
+        assert!(render(&alarm).contains("\r\nDESCRIPTION:Sett ut dunken\\; nå\r\n"));
+        alarm.before = Duration::hours(6);
+        alarm.action = AlarmAction::Audio;
+        assert_eq!(
+            render(&alarm),
+            "BEGIN:VALARM\r\nACTION:AUDIO\r\nTRIGGER:-PT6H\r\nEND:VALARM\r\n"
+        );
+        alarm.before = Duration::zero();
+        assert!(render(&alarm).contains("\r\nTRIGGER:PT0S\r\n"));
+    }
+
+    #[test]
+    fn test_when() {
+        let when = |before| Alarm::new(before, AlarmAction::Display).when();
+        assert_eq!(when(Duration::zero()), "i dag");
+        assert_eq!(when(Duration::hours(6)), "i morgen");
+        assert_eq!(when(Duration::days(1)), "i morgen");
+        assert_eq!(when(Duration::hours(25)), "om 2 dager");
+        assert_eq!(when(Duration::days(3)), "om 3 dager");
+    }
+
+    #[test]
+    fn test_parse_reminder() {
+        assert_eq!(parse_reminder("1d"), Ok(Duration::days(1)));
+        assert_eq!(parse_reminder(" 6H "), Ok(Duration::hours(6)));
+        assert_eq!(parse_reminder("90m"), Ok(Duration::minutes(90)));
+        for value in ["", "d", "1", "1w", "-1d", "1.5h", "æ"] {
+            assert!(parse_reminder(value).is_err(), "{value}");
+        }
+    }
+}
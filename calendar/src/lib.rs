@@ -1,8 +1,16 @@
-use core::{fmt, num::NonZeroU8};
-use std::io::Write;
+extern crate alloc;
+use alloc::borrow::Cow;
+use core::{
+    fmt,
+    num::{NonZeroU8, NonZeroUsize},
+};
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    io::Write,
+};
 
 use chrono::{
-    DateTime, Duration, NaiveDate, Utc,
+    DateTime, Datelike, Duration, Months, NaiveDate, NaiveDateTime, NaiveTime, Utc, Weekday,
     format::{DelayedFormat, StrftimeItems},
 };
 use ics::{
@@ -13,15 +21,157 @@ use ics::{
 use url::Url;
 use uuid::Uuid;
 
+pub mod alarm;
+pub mod omit;
+
+pub use alarm::{Alarm, AlarmAction, parse_reminder};
+pub use common::format::{self, OutputFormat, format_from_accept};
+pub use omit::{Omit, OmitProperty};
+
+/// `PRODID` for calendars not produced by a more specific tool.
+pub const DEFAULT_PRODID: &str = "-//Rizwold//Calendar//NO";
+
+/// How many years after `DTSTART` an `RDATE` or `EXDATE` may be, see
+/// [`Calendar::is_valid`].
+pub const MAX_RECURRENCE_YEARS: u32 = 100;
+
+/// `CALSCALE` written unless a calendar sets another or none.
+pub const DEFAULT_CALSCALE: &str = "GREGORIAN";
+
 #[derive(Debug, Clone)]
 pub struct Calendar {
     pub prodid: String,
     pub name: Option<String>,
     pub description: Option<String>,
     pub events: Vec<Event>,
+    pub options: RenderOptions,
+    /// `mailto:` addresses added as `ATTENDEE` to every event. With any
+    /// attendees and an [`Calendar::organizer`] the calendar is sent as
+    /// `METHOD:REQUEST` instead of `PUBLISH`.
+    pub attendees: Vec<String>,
+    /// `mailto:` address written as `ORGANIZER` on every event with
+    /// attendees. RFC 5546 requires it for `METHOD:REQUEST`, so attendees
+    /// are left out without it.
+    pub organizer: Option<String>,
+    /// CSS3 color name written as `COLOR` (RFC 7986).
+    pub color: Option<String>,
+    /// Suggested polling interval for subscribers, written as
+    /// `REFRESH-INTERVAL` (RFC 7986) and `X-PUBLISHED-TTL`.
+    pub refresh_interval: Option<Duration>,
+    /// Written as `CALSCALE`, normally [`DEFAULT_CALSCALE`]. Omitted when
+    /// `None`, for tools that reject the property.
+    pub calscale: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// Controls how a [`Calendar`] is rendered.
+#[derive(Debug, Clone, Copy, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct RenderOptions {
+    /// Omit non-standard `X-` prefixed properties.
+    pub strict_rfc: bool,
+    /// Only write the `VEVENT` components, without the `VCALENDAR` wrapper.
+    pub events_only: bool,
+    /// Restrict summary, name and description values to ASCII, see [`to_ascii`].
+    pub ascii: bool,
+    /// Truncate `SUMMARY` values longer than this many characters, ending
+    /// them with `…`. Glyphs are kept whole, so the result may be shorter.
+    pub max_summary_len: Option<NonZeroUsize>,
+    /// Omit `DTEND` on single-day events, which then default to one day.
+    /// Works around clients that show an exclusive `DTEND` as an extra day.
+    pub no_dtend: bool,
+    /// Break event `DESCRIPTION` lines at word boundaries to at most this
+    /// many characters, for clients that show folded lines as they are.
+    pub wrap_description: Option<NonZeroUsize>,
+    /// Omit optional properties to make the output smaller: `X-`
+    /// properties, `DESCRIPTION`, and on events `TRANSP`, `URL`, `GEO` and
+    /// `VALARM`.
+    pub compact: bool,
+    /// Optional properties to leave out, for clients that reject them.
+    pub omit: Omit,
+}
+
+impl RenderOptions {
+    /// Whether to write `property`, which is left out if listed in
+    /// [`Self::omit`], or by [`Self::compact`] or [`Self::strict_rfc`].
+    const fn writes(self, property: OmitProperty) -> bool {
+        let by_flags = match property {
+            OmitProperty::Transp
+            | OmitProperty::Url
+            | OmitProperty::Description
+            | OmitProperty::Valarm
+            | OmitProperty::Geo => self.compact,
+            OmitProperty::XProps => self.compact || self.strict_rfc,
+            OmitProperty::Method | OmitProperty::Calscale => false,
+        };
+        !by_flags && !self.omit.contains(property)
+    }
+
+    /// Whether to write non-standard `X-` properties.
+    const fn extensions(self) -> bool {
+        self.writes(OmitProperty::XProps)
+    }
+
+    fn text(self, value: &str) -> Cow<'_, str> {
+        if self.ascii {
+            Cow::Owned(to_ascii(value))
+        } else {
+            Cow::Borrowed(value)
+        }
+    }
+
+    fn summary(self, value: &str) -> Cow<'_, str> {
+        let text = self.text(value);
+        match self.max_summary_len {
+            Some(max) if text.chars().count() > max.get() => {
+                let chars: Vec<_> = text.char_indices().collect();
+                // Keep room for the ellipsis, and whole glyphs only
+                let cut = (1..max.get())
+                    .rev()
+                    .find(|&n| is_glyph_boundary(chars[n - 1].1, chars[n].1))
+                    .map_or(0, |n| chars[n].0);
+                let mut truncated = text[..cut].to_string();
+                truncated.push('…');
+                Cow::Owned(truncated)
+            }
+            _ => text,
+        }
+    }
+
+    fn description(self, value: &str) -> Cow<'_, str> {
+        let text = self.text(value);
+        match self.wrap_description {
+            Some(width) => Cow::Owned(word_wrap(&text, width.get())),
+            None => text,
+        }
+    }
+}
+
+/// Break lines in `text` at spaces so they are at most `width` characters,
+/// where possible. Words longer than `width` are kept whole.
+fn word_wrap(text: &str, width: usize) -> String {
+    let mut wrapped = String::with_capacity(text.len());
+    for (i, line) in text.split('\n').enumerate() {
+        if i > 0 {
+            wrapped.push('\n');
+        }
+        let mut column = 0;
+        for (j, word) in line.split(' ').enumerate() {
+            let len = word.chars().count();
+            if j > 0 && column > 0 && column + 1 + len > width {
+                wrapped.push('\n');
+                column = 0;
+            } else if j > 0 {
+                wrapped.push(' ');
+                column += 1;
+            }
+            wrapped.push_str(word);
+            column += len;
+        }
+    }
+    wrapped
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Event {
     pub uid: uuid::Uuid,
     pub dtstamp: DateTime<Utc>,
@@ -35,51 +185,458 @@ pub struct Event {
     pub description: Option<String>,
     pub url: Option<Url>,
     pub recurrence_id: Option<NaiveDate>,
+    /// The override replaces this and all following occurrences, written
+    /// as `RANGE=THISANDFUTURE` on `RECURRENCE-ID`.
+    pub this_and_future: bool,
+    /// Latitude and longitude in degrees, written as `GEO`.
+    pub geo: Option<(f64, f64)>,
+    /// Reminder before the event, written as `VALARM`.
+    pub alarm: Option<Alarm>,
+    /// Non-standard `X-` properties as name and value pairs.
+    pub extensions: Vec<(String, String)>,
 }
 
 impl Calendar {
     /// # Errors
-    pub fn write<W>(&self, writer: W) -> Result<(), std::io::Error>
+    pub fn write<W>(&self, mut writer: W) -> Result<(), std::io::Error>
     where
         W: Write,
     {
-        let cal: ICalendar<'_> = self.into();
-        cal.write(writer)
+        write!(writer, "{self}")
     }
-}
 
-impl fmt::Display for Calendar {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let cal: ICalendar<'_> = self.into();
-        cal.fmt(f)
+    /// Check the events for mistakes that would make the output wrong:
+    /// a nil UID, an empty `SUMMARY`, a `DTEND` that is not after
+    /// `DTSTART`, and an `RDATE` or `EXDATE` before `DTSTART` or more than
+    /// [`MAX_RECURRENCE_YEARS`] after it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a message for every problem found.
+    pub fn is_valid(&self) -> Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        for (i, event) in self.events.iter().enumerate() {
+            let name = format!("Event {i} ({})", event.uid);
+            if event.uid.is_nil() {
+                problems.push(format!("{name}: UID is empty"));
+            }
+            if event.summary.trim().is_empty() {
+                problems.push(format!("{name}: SUMMARY is empty"));
+            }
+            let end = event
+                .date
+                .checked_add_signed(Duration::days(i64::from(event.duration.get())));
+            if end.is_none_or(|end| end <= event.date) {
+                problems.push(format!("{name}: DTEND is not after DTSTART"));
+            }
+            let latest = event
+                .date
+                .checked_add_months(Months::new(12 * MAX_RECURRENCE_YEARS))
+                .unwrap_or(NaiveDate::MAX);
+            for (property, dates) in [("RDATE", &event.rdates), ("EXDATE", &event.exdates)] {
+                for date in dates
+                    .iter()
+                    .filter(|date| !(event.date..=latest).contains(date))
+                {
+                    problems.push(format!(
+                        "{name}: {property} {date} is out of range for DTSTART {}",
+                        event.date
+                    ));
+                }
+            }
+        }
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
     }
-}
 
-impl<'a> From<&'a Calendar> for ics::ICalendar<'a> {
-    fn from(value: &'a Calendar) -> Self {
-        let mut cal = ICalendar::new("2.0", &value.prodid);
+    /// Build the [`ICalendar`], allowing callers to add components or
+    /// properties before writing it.
+    ///
+    /// The `ics` crate folds long lines between any two code points, which
+    /// can split a glyph such as an emoji with a variation selector. Only
+    /// the [`Display`](fmt::Display) implementation and [`Calendar::write`]
+    /// fold on glyph boundaries.
+    #[must_use]
+    pub fn to_ical(&self) -> ICalendar<'_> {
+        let mut cal = ICalendar::new("2.0", &self.prodid);
         // cal.push(Property::new("X-WR-TIMEZONE", "Europe/Oslo"));
-        cal.push(CalScale::new("GREGORIAN"));
-        cal.push(Method::new("PUBLISH"));
-        if let Some(name) = &value.name {
+        if let Some(calscale) = self
+            .calscale
+            .as_ref()
+            .filter(|_| self.options.writes(OmitProperty::Calscale))
+        {
+            cal.push(CalScale::new(calscale.as_str()));
+        }
+        if self.options.writes(OmitProperty::Method) {
+            cal.push(Method::new(if self.invitation().is_some() {
+                "REQUEST"
+            } else {
+                "PUBLISH"
+            }));
+        }
+        let extensions = self.options.extensions();
+        if let Some(name) = &self.name {
+            let name = self.options.text(name);
             cal.push(Name::new(ics::escape_text(name.clone())));
-            cal.push(Property::new(
-                "X-WR-CALNAME",
-                ics::escape_text(name.clone()),
-            ));
+            if extensions {
+                cal.push(Property::new("X-WR-CALNAME", ics::escape_text(name)));
+            }
         }
-        if let Some(desc) = &value.description {
+        if let Some(desc) = self
+            .description
+            .as_ref()
+            .filter(|_| self.options.writes(OmitProperty::Description))
+        {
+            let desc = self.options.text(desc);
             cal.push(Description::new(ics::escape_text(desc.clone())));
-            cal.push(Property::new(
-                "X-WR-CALDESC",
-                ics::escape_text(desc.clone()),
-            ));
+            if extensions {
+                cal.push(Property::new("X-WR-CALDESC", ics::escape_text(desc)));
+            }
+        }
+        // Only known names, as the value is not escaped
+        if let Some(color) = self
+            .color
+            .as_deref()
+            .and_then(|color| parse_color(color).ok())
+        {
+            cal.push(Property::new("COLOR", color));
         }
-        for e in &value.events {
-            cal.add_event(e.into());
+        if let Some(interval) = self.refresh_interval {
+            let value = format_duration(interval);
+            let mut prop = Property::new("REFRESH-INTERVAL", value.clone());
+            prop.add(ics::parameters::Value::new("DURATION"));
+            cal.push(prop);
+            if extensions {
+                cal.push(Property::new("X-PUBLISHED-TTL", value));
+            }
+        }
+        for e in &self.events {
+            cal.add_event(e.to_ics(self.options, self.invitation()));
         }
         cal
     }
+
+    /// The organizer and attendees written on every event, if the calendar
+    /// is an invitation.
+    fn invitation(&self) -> Option<(&str, &[String])> {
+        self.organizer
+            .as_deref()
+            .filter(|_| !self.attendees.is_empty())
+            .map(|organizer| (organizer, self.attendees.as_slice()))
+    }
+
+    /// Add the events of `other`.
+    ///
+    /// Events are identified by `(uid, recurrence_id)`. Of two events with
+    /// the same identity, the one with the higher `sequence` is kept, or the
+    /// one in `self` if they are equal. Calendar properties of `self` are
+    /// kept, those that are `None` are taken from `other`.
+    pub fn merge(&mut self, other: Self) {
+        let mut index: HashMap<_, _> = self
+            .events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| ((event.uid, event.recurrence_id), i))
+            .collect();
+        for event in other.events {
+            match index.entry((event.uid, event.recurrence_id)) {
+                Entry::Occupied(entry) => {
+                    let existing = &mut self.events[*entry.get()];
+                    if event.sequence > existing.sequence {
+                        *existing = event;
+                    }
+                }
+                Entry::Vacant(entry) => {
+                    entry.insert(self.events.len());
+                    self.events.push(event);
+                }
+            }
+        }
+        self.name = self.name.take().or(other.name);
+        self.description = self.description.take().or(other.description);
+        self.color = self.color.take().or(other.color);
+        self.organizer = self.organizer.take().or(other.organizer);
+        self.refresh_interval = self.refresh_interval.or(other.refresh_interval);
+    }
+
+    /// Add `version` to the product name in `PRODID`, so a calendar shows
+    /// which build produced it. A `PRODID` like `-//Owner//Product//EN`
+    /// becomes `-//Owner//Product version//EN`.
+    pub fn stamp_version(&mut self, version: &str) {
+        self.prodid = match self.prodid.rsplit_once("//") {
+            Some((product, language)) if product.contains("//") => {
+                format!("{product} {version}//{language}")
+            }
+            _ => format!("{} {version}", self.prodid),
+        };
+    }
+
+    /// Add `prefix` and `suffix` to the summary of every event, such as a
+    /// tag telling calendars apart in a client showing several.
+    pub fn decorate_summaries(&mut self, prefix: &str, suffix: &str) {
+        if prefix.is_empty() && suffix.is_empty() {
+            return;
+        }
+        for event in &mut self.events {
+            event.summary = format!("{prefix}{}{suffix}", event.summary);
+        }
+    }
+
+    /// Set `alarm` as the reminder of every event.
+    pub fn set_alarm(&mut self, alarm: &Alarm) {
+        for event in &mut self.events {
+            event.alarm = Some(alarm.clone());
+        }
+    }
+
+    /// Keep only events starting on one of `only`, or on any day if `only`
+    /// is empty, leaving out Saturdays and Sundays with `skip_weekends`.
+    pub fn retain_weekdays(&mut self, only: &[Weekday], skip_weekends: bool) {
+        self.events.retain(|event| {
+            let weekday = event.date.weekday();
+            (only.is_empty() || only.contains(&weekday))
+                && !(skip_weekends && matches!(weekday, Weekday::Sat | Weekday::Sun))
+        });
+    }
+
+    /// Replace each non-recurring event lasting several days with one event
+    /// per day.
+    ///
+    /// The day events get UIDs derived from the original UID and the day
+    /// index. Recurring events and overrides of a single occurrence are kept
+    /// as they are, expand them first with [`Calendar::expand_recurring`].
+    pub fn flatten_multiday(&mut self) {
+        for event in core::mem::take(&mut self.events) {
+            let recurring =
+                event.rrule.is_some() || !event.rdates.is_empty() || event.recurrence_id.is_some();
+            if event.duration == NonZeroU8::MIN || recurring {
+                self.events.push(event);
+                continue;
+            }
+            for day in 0..event.duration.get() {
+                self.events.push(Event {
+                    uid: Uuid::new_v5(&event.uid, day.to_string().as_bytes()),
+                    date: event.date + Duration::days(i64::from(day)),
+                    duration: NonZeroU8::MIN,
+                    ..event.clone()
+                });
+            }
+        }
+    }
+
+    /// Remove events starting before `cutoff`.
+    ///
+    /// A recurring event starting earlier instead starts at its first
+    /// occurrence on or after `cutoff`, see [`Event::starting_from`].
+    pub fn drop_before(&mut self, cutoff: NaiveDate) {
+        self.events = core::mem::take(&mut self.events)
+            .into_iter()
+            .filter_map(|event| event.starting_from(cutoff))
+            .collect();
+    }
+
+    /// Replace each recurring event with its first `limit` occurrences as
+    /// separate events, for clients without `RRULE` support.
+    ///
+    /// Occurrence UIDs are derived from the master UID and the date, and an
+    /// override (an event with `RECURRENCE-ID`) gets the UID of the occurrence
+    /// it replaces. Non-recurring events are kept as they are.
+    pub fn expand_recurring(&mut self, limit: usize) {
+        let occurrence_uid = |uid: Uuid, date: NaiveDate| {
+            Uuid::new_v5(&uid, date.format("%Y%m%d").to_string().as_bytes())
+        };
+        let overridden = self.overridden();
+        for event in core::mem::take(&mut self.events) {
+            if let Some(id) = event.recurrence_id {
+                self.events.push(Event {
+                    uid: occurrence_uid(event.uid, id),
+                    recurrence_id: None,
+                    this_and_future: false,
+                    ..event
+                });
+            } else if event.rrule.is_some() || !event.rdates.is_empty() {
+                let moved = overridden.get(&event.uid).map_or(&[][..], Vec::as_slice);
+                let mut dates = event.occurrences(limit.saturating_add(moved.len()));
+                dates.retain(|date| !moved.contains(date));
+                dates.truncate(limit);
+                for date in dates {
+                    self.events.push(Event {
+                        uid: occurrence_uid(event.uid, date),
+                        date,
+                        rrule: None,
+                        rdates: Vec::new(),
+                        exdates: Vec::new(),
+                        ..event.clone()
+                    });
+                }
+            } else {
+                self.events.push(event);
+            }
+        }
+    }
+
+    /// Dates of the first `limit` occurrences of the series `uid`, in order.
+    ///
+    /// Like [`Event::occurrences`] of the series, with each occurrence that
+    /// has an override moved to the date of the override.
+    #[must_use]
+    pub fn occurrences(&self, uid: Uuid, limit: usize) -> Vec<NaiveDate> {
+        let overrides: Vec<_> = self
+            .events
+            .iter()
+            .filter(|event| event.uid == uid)
+            .filter_map(|event| event.recurrence_id.map(|id| (id, event.date)))
+            .collect();
+        let mut dates = self
+            .events
+            .iter()
+            .find(|event| event.uid == uid && event.recurrence_id.is_none())
+            .map(|event| event.occurrences(limit.saturating_add(overrides.len())))
+            .unwrap_or_default();
+        dates.retain(|date| !overrides.iter().any(|(id, _)| id == date));
+        dates.extend(overrides.iter().map(|(_, date)| *date));
+        dates.sort_unstable();
+        dates.dedup();
+        dates.truncate(limit);
+        dates
+    }
+
+    /// The `RECURRENCE-ID` dates of the overrides of each series.
+    fn overridden(&self) -> HashMap<Uuid, Vec<NaiveDate>> {
+        let mut overridden: HashMap<Uuid, Vec<NaiveDate>> = HashMap::new();
+        for event in &self.events {
+            if let Some(id) = event.recurrence_id {
+                overridden.entry(event.uid).or_default().push(id);
+            }
+        }
+        overridden
+    }
+}
+
+impl fmt::Display for Calendar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ical = if self.options.events_only {
+            self.events
+                .iter()
+                .map(|e| e.to_ics(self.options, self.invitation()).to_string())
+                .collect()
+        } else {
+            self.to_ical().to_string()
+        };
+        refold(f, &ical)
+    }
+}
+
+/// Maximum octets in a content line, not counting the line break.
+const LINE_LIMIT: usize = 75;
+
+/// Whether `c` belongs to the glyph of the character before it, like
+/// combining marks, variation selectors, emoji modifiers and zero-width
+/// joiners.
+const fn is_extending(c: char) -> bool {
+    matches!(
+        c,
+        '\u{0300}'..='\u{036F}'
+            | '\u{1AB0}'..='\u{1AFF}'
+            | '\u{1DC0}'..='\u{1DFF}'
+            | '\u{200D}'
+            | '\u{20D0}'..='\u{20FF}'
+            | '\u{FE00}'..='\u{FE0F}'
+            | '\u{FE20}'..='\u{FE2F}'
+            | '\u{1F3FB}'..='\u{1F3FF}'
+            | '\u{E0020}'..='\u{E007F}'
+            | '\u{E0100}'..='\u{E01EF}'
+    )
+}
+
+/// Whether a glyph may end between `prev` and `c`: not before a character
+/// extending it, see [`is_extending`], nor after a zero-width joiner.
+const fn is_glyph_boundary(prev: char, c: char) -> bool {
+    !is_extending(c) && prev != '\u{200D}'
+}
+
+/// The byte index to fold `line` at to keep the first part within `limit`
+/// octets. Prefers to fold on a glyph boundary, see [`is_glyph_boundary`].
+/// Sequences too long for a line are split at the last character boundary.
+fn fold_boundary(line: &str, limit: usize) -> usize {
+    if line.len() <= limit {
+        return line.len();
+    }
+    let mut boundary = None;
+    let mut fallback = 0;
+    let mut prev = None;
+    for (i, c) in line.char_indices().take_while(|(i, _)| *i <= limit) {
+        if i > 0 {
+            fallback = i;
+            if prev.is_some_and(|prev| is_glyph_boundary(prev, c)) {
+                boundary = Some(i);
+            }
+        }
+        prev = Some(c);
+    }
+    boundary.unwrap_or(fallback)
+}
+
+/// Join folded content lines, which continue after a CRLF and a space or
+/// tab.
+fn unfold(ical: &str) -> String {
+    ical.replace("\r\n ", "").replace("\r\n\t", "")
+}
+
+/// A view of `ics` for reading during development, with each content line
+/// unfolded and indented by how deeply its component is nested.
+///
+/// Every line starts with `#`, so the result is never mistaken for an
+/// iCalendar file.
+#[must_use]
+pub fn explain(ics: &str) -> String {
+    let mut out = String::from("# Debug view, not iCalendar: unfolded and indented\n");
+    let mut depth = 0_usize;
+    for line in unfold(ics).split_terminator("\r\n") {
+        if line.starts_with("END:") {
+            depth = depth.saturating_sub(1);
+        }
+        out.push_str("# ");
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(line);
+        out.push('\n');
+        if line.starts_with("BEGIN:") {
+            depth += 1;
+        }
+    }
+    out
+}
+
+/// Write `ical` with its content lines unfolded and folded again at
+/// [`fold_boundary`], which keeps glyphs whole where the `ics` crate only
+/// keeps code points whole.
+fn refold(f: &mut impl fmt::Write, ical: &str) -> fmt::Result {
+    for line in unfold(ical).split_terminator("\r\n") {
+        let mut rest = line;
+        let mut limit = LINE_LIMIT;
+        loop {
+            let boundary = fold_boundary(rest, limit);
+            f.write_str(&rest[..boundary])?;
+            rest = &rest[boundary..];
+            if rest.is_empty() {
+                break;
+            }
+            f.write_str("\r\n ")?;
+            // The leading space counts towards the limit
+            limit = LINE_LIMIT - 1;
+        }
+        f.write_str("\r\n")?;
+    }
+    Ok(())
+}
+
+impl<'a> From<&'a Calendar> for ics::ICalendar<'a> {
+    fn from(value: &'a Calendar) -> Self {
+        value.to_ical()
+    }
 }
 
 macro_rules! date_property {
@@ -94,46 +651,778 @@ macro_rules! date_property {
 
 impl<'a> From<&'a Event> for ics::Event<'a> {
     fn from(value: &'a Event) -> Self {
+        value.to_ics(RenderOptions::default(), None)
+    }
+}
+
+/// Builds an [`Event`] from the required properties, with the rest
+/// defaulted, see [`Event::builder`].
+#[derive(Debug, Clone)]
+#[must_use]
+pub struct EventBuilder {
+    event: Event,
+}
+
+impl EventBuilder {
+    /// Number of days, one by default.
+    pub const fn duration(mut self, duration: NonZeroU8) -> Self {
+        self.event.duration = duration;
+        self
+    }
+
+    pub fn rrule(mut self, rrule: Option<rrule::RRule>) -> Self {
+        self.event.rrule = rrule;
+        self
+    }
+
+    pub fn rdates(mut self, rdates: Vec<NaiveDate>) -> Self {
+        self.event.rdates = rdates;
+        self
+    }
+
+    pub fn exdates(mut self, exdates: Vec<NaiveDate>) -> Self {
+        self.event.exdates = exdates;
+        self
+    }
+
+    pub const fn sequence(mut self, sequence: i64) -> Self {
+        self.event.sequence = sequence;
+        self
+    }
+
+    pub fn description(mut self, description: Option<String>) -> Self {
+        self.event.description = description;
+        self
+    }
+
+    pub fn url(mut self, url: Option<Url>) -> Self {
+        self.event.url = url;
+        self
+    }
+
+    /// Make the event an override of the occurrence on `date`, and of all
+    /// following occurrences with `this_and_future`.
+    pub const fn recurrence_id(mut self, date: NaiveDate, this_and_future: bool) -> Self {
+        self.event.recurrence_id = Some(date);
+        self.event.this_and_future = this_and_future;
+        self
+    }
+
+    pub const fn geo(mut self, geo: Option<(f64, f64)>) -> Self {
+        self.event.geo = geo;
+        self
+    }
+
+    pub fn alarm(mut self, alarm: Option<Alarm>) -> Self {
+        self.event.alarm = alarm;
+        self
+    }
+
+    /// Add a non-standard `X-` property.
+    pub fn extension(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.event.extensions.push((name.into(), value.into()));
+        self
+    }
+
+    /// # Errors
+    ///
+    /// Returns a message if the summary is blank or an extension name does
+    /// not start with `X-`.
+    pub fn build(self) -> Result<Event, String> {
+        if self.event.summary.trim().is_empty() {
+            return Err(format!("Event {} has an empty summary", self.event.uid));
+        }
+        if let Some((name, _)) = self
+            .event
+            .extensions
+            .iter()
+            .find(|(name, _)| !name.to_ascii_uppercase().starts_with("X-"))
+        {
+            return Err(format!("Extension {name} must start with X-"));
+        }
+        Ok(self.event)
+    }
+}
+
+impl Event {
+    /// Start building an event with the required properties.
+    pub fn builder(
+        uid: Uuid,
+        date: NaiveDate,
+        summary: impl Into<String>,
+        dtstamp: DateTime<Utc>,
+    ) -> EventBuilder {
+        EventBuilder {
+            event: Self {
+                uid,
+                dtstamp,
+                duration: NonZeroU8::MIN,
+                rrule: None,
+                rdates: Vec::new(),
+                exdates: Vec::new(),
+                sequence: 0,
+                date,
+                summary: summary.into(),
+                description: None,
+                url: None,
+                recurrence_id: None,
+                this_and_future: false,
+                geo: None,
+                alarm: None,
+                extensions: Vec::new(),
+            },
+        }
+    }
+
+    /// Dates of the first `limit` occurrences, in order.
+    ///
+    /// Combines `DTSTART`, the expanded `RRULE` and `RDATE`s, with
+    /// `EXDATE`s removed. An override (an event with `RECURRENCE-ID`) is a
+    /// single occurrence. To move overridden occurrences of a series, see
+    /// [`Calendar::occurrences`].
+    #[must_use]
+    pub fn occurrences(&self, limit: usize) -> Vec<NaiveDate> {
+        let mut dates = vec![self.date];
+        if self.recurrence_id.is_some() {
+            dates.truncate(limit);
+            return dates;
+        }
+        if let Some(rrule) = &self.rrule {
+            // Expand enough dates to still have `limit` after removing exdates
+            let count = u16::try_from(limit.saturating_add(self.exdates.len())).unwrap_or(u16::MAX);
+            let set =
+                rrule::RRuleSet::new(start_of_day(self.date, RRULE_TIMEZONE)).rrule(rrule.clone());
+            dates.extend(set.all(count).dates.iter().map(DateTime::date_naive));
+        }
+        dates.extend(&self.rdates);
+        dates.retain(|date| !self.exdates.contains(date));
+        dates.sort_unstable();
+        dates.dedup();
+        dates.truncate(limit);
+        dates
+    }
+
+    /// The event without occurrences before `cutoff`, or `None` if it has
+    /// none left.
+    ///
+    /// `DTSTART` of a recurring event is moved to the first `RRULE`
+    /// occurrence on or after `cutoff`, reducing any `COUNT` by the skipped
+    /// occurrences. Earlier `RDATE`s and `EXDATE`s are removed.
+    #[must_use]
+    pub fn starting_from(mut self, cutoff: NaiveDate) -> Option<Self> {
+        if self.date >= cutoff {
+            return Some(self);
+        }
+        self.rdates.retain(|date| *date >= cutoff);
+        self.rdates.sort_unstable();
+        self.exdates.retain(|date| *date >= cutoff);
+        let first = self.rrule.take().and_then(|rrule| {
+            let dt_start = start_of_day(self.date, RRULE_TIMEZONE);
+            let set = rrule::RRuleSet::new(dt_start).rrule(rrule.clone());
+            let date = set
+                .after(start_of_day(cutoff, RRULE_TIMEZONE))
+                .all(1)
+                .dates
+                .first()?
+                .date_naive();
+            let Some(count) = rrule.get_count() else {
+                return Some((date, rrule));
+            };
+            let limit = u16::try_from(count).unwrap_or(u16::MAX);
+            let set = rrule::RRuleSet::new(dt_start).rrule(rrule.clone());
+            let dates = set.all(limit).dates;
+            let skipped = dates.iter().filter(|dt| dt.date_naive() < cutoff).count();
+            let count = count.saturating_sub(u32::try_from(skipped).unwrap_or(u32::MAX));
+            canonical_rrule(&rrule)
+                .parse::<rrule::RRule<rrule::Unvalidated>>()
+                .ok()?
+                .count(count)
+                .validate(start_of_day(date, RRULE_TIMEZONE))
+                .ok()
+                .map(|rrule| (date, rrule))
+        });
+        if let Some((date, rrule)) = first {
+            self.date = date;
+            self.rrule = Some(rrule);
+        } else if self.rdates.is_empty() {
+            return None;
+        } else {
+            self.date = self.rdates.remove(0);
+        }
+        Some(self)
+    }
+
+    /// The event without occurrences on or after `end`, or `None` if it has
+    /// none left.
+    ///
+    /// An `RRULE` gets an `UNTIL` the day before `end`, or a reduced
+    /// `COUNT` if it has one. Later `RDATE`s and `EXDATE`s are removed.
+    #[must_use]
+    pub fn ending_before(mut self, end: NaiveDate) -> Option<Self> {
+        if self.date >= end {
+            return None;
+        }
+        self.rdates.retain(|date| *date < end);
+        self.exdates.retain(|date| *date < end);
+        if let Some(rrule) = self.rrule.take() {
+            let rule = canonical_rrule(&rrule).parse::<rrule::RRule<rrule::Unvalidated>>();
+            let rule = match (rule, rrule.get_count()) {
+                (Ok(rule), Some(count)) => {
+                    let limit = u16::try_from(count).unwrap_or(u16::MAX);
+                    let set =
+                        rrule::RRuleSet::new(start_of_day(self.date, RRULE_TIMEZONE)).rrule(rrule);
+                    let dates = set.all(limit).dates;
+                    let kept = dates.iter().filter(|dt| dt.date_naive() < end).count();
+                    Ok(rule.count(u32::try_from(kept).unwrap_or(u32::MAX)))
+                }
+                (Ok(rule), None) => {
+                    let until = start_of_day(end, RRULE_TIMEZONE) - Duration::seconds(1);
+                    Ok(rule.until(until.with_timezone(&rrule::Tz::UTC)))
+                }
+                (Err(err), _) => Err(err),
+            };
+            self.rrule = rule
+                .and_then(|rule| rule.validate(start_of_day(self.date, RRULE_TIMEZONE)))
+                .ok();
+        }
+        Some(self)
+    }
+
+    fn to_ics<'a>(
+        &'a self,
+        options: RenderOptions,
+        invitation: Option<(&'a str, &'a [String])>,
+    ) -> ics::Event<'a> {
         let mut e = ics::Event::new(
-            format_uid(value.uid),
-            format_timestamp(&value.dtstamp).to_string(),
-        );
-        e.push(Sequence::new(value.sequence.to_string()));
-        e.push(date_property!(DtStart, value.date));
-        e.push(date_property!(
-            DtEnd,
-            value.date + Duration::days(i64::from(value.duration.get()))
-        ));
-        if let Some(id) = &value.recurrence_id {
-            e.push(date_property!(RecurrenceID, *id));
+            format_uid(self.uid),
+            format_timestamp(&self.dtstamp).to_string(),
+        );
+        e.push(Sequence::new(self.sequence.to_string()));
+        e.push(date_property!(DtStart, self.date));
+        if !(options.no_dtend && self.duration == NonZeroU8::MIN) {
+            e.push(date_property!(
+                DtEnd,
+                self.date + Duration::days(i64::from(self.duration.get()))
+            ));
         }
-        if let Some(rrule) = &value.rrule {
-            e.push(RRule::new(rrule.to_string()));
+        if let Some(id) = &self.recurrence_id {
+            let mut prop = date_property!(RecurrenceID, *id);
+            if self.this_and_future {
+                prop.append(ics::parameters!("RANGE" => "THISANDFUTURE"));
+            }
+            e.push(prop);
         }
-        for exdate in &value.exdates {
-            e.push(date_property!(ExDate, *exdate));
+        if let Some(rrule) = &self.rrule {
+            e.push(RRule::new(date_until(&canonical_rrule(rrule))));
         }
-        for rdate in &value.rdates {
-            e.push(date_property!(RDate, *rdate));
+        for exdate in sorted_unique(&self.exdates) {
+            e.push(date_property!(ExDate, exdate));
         }
-        e.push(Summary::new(ics::escape_text(&value.summary)));
-        e.push(Transp::transparent());
-        if let Some(url) = &value.url {
+        for rdate in sorted_unique(&self.rdates) {
+            e.push(date_property!(RDate, rdate));
+        }
+        e.push(Summary::new(ics::escape_text(
+            options.summary(&self.summary),
+        )));
+        if options.writes(OmitProperty::Transp) {
+            e.push(Transp::transparent());
+        }
+        if let Some(url) = self
+            .url
+            .as_ref()
+            .filter(|_| options.writes(OmitProperty::Url))
+        {
             e.push(properties::URL::new(url.to_string()));
         }
-        if let Some(description) = &value.description {
-            e.push(Description::new(ics::escape_text(description)));
+        if let Some((lat, lon)) = self.geo.filter(|_| options.writes(OmitProperty::Geo)) {
+            e.push(properties::Geo::new(format!("{lat};{lon}")));
+        }
+        if let Some(description) = self
+            .description
+            .as_ref()
+            .filter(|_| options.writes(OmitProperty::Description))
+        {
+            e.push(Description::new(ics::escape_text(
+                options.description(description),
+            )));
+        }
+        if let Some((organizer, attendees)) = invitation {
+            e.push(mailto_property(
+                properties::Organizer::new(organizer).into(),
+                organizer,
+            ));
+            for attendee in attendees {
+                let mut prop = mailto_property(
+                    properties::Attendee::new(attendee.as_str()).into(),
+                    attendee,
+                );
+                prop.append(ics::parameters!("RSVP" => "FALSE"));
+                e.push(prop);
+            }
+        }
+        if options.extensions() {
+            for (name, text) in &self.extensions {
+                e.push(Property::new(
+                    name.as_str(),
+                    ics::escape_text(text.as_str()),
+                ));
+            }
+        }
+        if let Some(alarm) = self
+            .alarm
+            .as_ref()
+            .filter(|_| options.writes(OmitProperty::Valarm))
+        {
+            e.add_alarm(alarm.to_ics(&self.summary, options));
         }
 
         e
     }
 }
 
+/// `dates` sorted and without duplicates, so output does not depend on
+/// the order they were added in.
+fn sorted_unique(dates: &[NaiveDate]) -> Vec<NaiveDate> {
+    let mut dates = dates.to_vec();
+    dates.sort_unstable();
+    dates.dedup();
+    dates
+}
+
+/// Parse a date given as `2024-03-01`, `01.03.2024` or `20240301`.
+///
+/// Intended as a `value_parser` for command line date arguments.
+///
+/// # Errors
+///
+/// Returns a message describing the accepted formats if `value` is not a
+/// valid date in any of them.
+pub fn parse_flexible_date(value: &str) -> Result<NaiveDate, String> {
+    const FORMATS: [&str; 3] = ["%Y-%m-%d", "%d.%m.%Y", "%Y%m%d"];
+    let value = value.trim();
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDate::parse_from_str(value, format).ok())
+        .ok_or_else(|| {
+            format!("Invalid date '{value}', expected YYYY-MM-DD, DD.MM.YYYY or YYYYMMDD")
+        })
+}
+
+/// Check the structure of a rendered calendar and count its events.
+///
+/// A sanity check of our own output, not a full validator: `ics` must be
+/// one CRLF terminated `VCALENDAR` with folded lines and at least one
+/// complete `VEVENT`.
+///
+/// # Errors
+///
+/// Returns a message describing the first problem found.
+pub fn check_rendered(ics: &str) -> Result<usize, String> {
+    let body = ics
+        .strip_prefix("BEGIN:VCALENDAR\r\n")
+        .and_then(|body| body.strip_suffix("END:VCALENDAR\r\n"))
+        .ok_or("Not a VCALENDAR")?;
+    let mut events = 0;
+    let mut in_event = false;
+    for line in body.split_terminator("\r\n") {
+        if line.len() > 75 {
+            return Err(format!("Line longer than 75 octets: {line}"));
+        }
+        match line {
+            "BEGIN:VEVENT" if !in_event => in_event = true,
+            "END:VEVENT" if in_event => {
+                in_event = false;
+                events += 1;
+            }
+            "BEGIN:VEVENT" | "END:VEVENT" => return Err(format!("Unexpected {line}")),
+            _ => {}
+        }
+    }
+    if in_event {
+        return Err("Unterminated VEVENT".to_string());
+    }
+    if events == 0 {
+        return Err("No events".to_string());
+    }
+    Ok(events)
+}
+
+/// Check a calendar rendered by a `selftest` command with
+/// [`check_rendered`], expecting `count` events, and return it.
+///
+/// # Errors
+///
+/// Returns a message describing the first problem found.
+pub fn check_selftest(outputs: &[String], count: usize) -> Result<String, String> {
+    let ics = outputs.concat();
+    let events = check_rendered(&ics)?;
+    if events != count {
+        return Err(format!("Expected {count} events, found {events}"));
+    }
+    Ok(ics)
+}
+
+/// Parse a weekday given as `mon` or `monday`, ignoring case.
+///
+/// Intended as a `value_parser` for command line weekday arguments.
+///
+/// # Errors
+///
+/// Returns a message if `value` is not a weekday.
+pub fn parse_weekday(value: &str) -> Result<Weekday, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("Invalid weekday '{value}', expected mon, tue, ..., sun"))
+}
+
+/// Parse a `CALSCALE` value, which must be an IANA token of letters,
+/// digits and `-`. The value is upper-cased.
+///
+/// Intended as a `value_parser` for command line calendar scale arguments.
+///
+/// # Errors
+///
+/// Returns a message if `value` is not a valid token.
+pub fn parse_calscale(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        Ok(value.to_ascii_uppercase())
+    } else {
+        Err(format!(
+            "Invalid calendar scale '{value}', expected letters, digits and '-'"
+        ))
+    }
+}
+
+/// The CSS3 color keywords, sorted, as allowed in `COLOR` (RFC 7986).
+const CSS3_COLORS: [&str; 147] = [
+    "aliceblue",
+    "antiquewhite",
+    "aqua",
+    "aquamarine",
+    "azure",
+    "beige",
+    "bisque",
+    "black",
+    "blanchedalmond",
+    "blue",
+    "blueviolet",
+    "brown",
+    "burlywood",
+    "cadetblue",
+    "chartreuse",
+    "chocolate",
+    "coral",
+    "cornflowerblue",
+    "cornsilk",
+    "crimson",
+    "cyan",
+    "darkblue",
+    "darkcyan",
+    "darkgoldenrod",
+    "darkgray",
+    "darkgreen",
+    "darkgrey",
+    "darkkhaki",
+    "darkmagenta",
+    "darkolivegreen",
+    "darkorange",
+    "darkorchid",
+    "darkred",
+    "darksalmon",
+    "darkseagreen",
+    "darkslateblue",
+    "darkslategray",
+    "darkslategrey",
+    "darkturquoise",
+    "darkviolet",
+    "deeppink",
+    "deepskyblue",
+    "dimgray",
+    "dimgrey",
+    "dodgerblue",
+    "firebrick",
+    "floralwhite",
+    "forestgreen",
+    "fuchsia",
+    "gainsboro",
+    "ghostwhite",
+    "gold",
+    "goldenrod",
+    "gray",
+    "green",
+    "greenyellow",
+    "grey",
+    "honeydew",
+    "hotpink",
+    "indianred",
+    "indigo",
+    "ivory",
+    "khaki",
+    "lavender",
+    "lavenderblush",
+    "lawngreen",
+    "lemonchiffon",
+    "lightblue",
+    "lightcoral",
+    "lightcyan",
+    "lightgoldenrodyellow",
+    "lightgray",
+    "lightgreen",
+    "lightgrey",
+    "lightpink",
+    "lightsalmon",
+    "lightseagreen",
+    "lightskyblue",
+    "lightslategray",
+    "lightslategrey",
+    "lightsteelblue",
+    "lightyellow",
+    "lime",
+    "limegreen",
+    "linen",
+    "magenta",
+    "maroon",
+    "mediumaquamarine",
+    "mediumblue",
+    "mediumorchid",
+    "mediumpurple",
+    "mediumseagreen",
+    "mediumslateblue",
+    "mediumspringgreen",
+    "mediumturquoise",
+    "mediumvioletred",
+    "midnightblue",
+    "mintcream",
+    "mistyrose",
+    "moccasin",
+    "navajowhite",
+    "navy",
+    "oldlace",
+    "olive",
+    "olivedrab",
+    "orange",
+    "orangered",
+    "orchid",
+    "palegoldenrod",
+    "palegreen",
+    "paleturquoise",
+    "palevioletred",
+    "papayawhip",
+    "peachpuff",
+    "peru",
+    "pink",
+    "plum",
+    "powderblue",
+    "purple",
+    "red",
+    "rosybrown",
+    "royalblue",
+    "saddlebrown",
+    "salmon",
+    "sandybrown",
+    "seagreen",
+    "seashell",
+    "sienna",
+    "silver",
+    "skyblue",
+    "slateblue",
+    "slategray",
+    "slategrey",
+    "snow",
+    "springgreen",
+    "steelblue",
+    "tan",
+    "teal",
+    "thistle",
+    "tomato",
+    "turquoise",
+    "violet",
+    "wheat",
+    "white",
+    "whitesmoke",
+    "yellow",
+    "yellowgreen",
+];
+
+/// Parse a CSS3 color name for `COLOR`, ignoring case. The value is
+/// lower-cased.
+///
+/// Intended as a `value_parser` for command line color arguments.
+///
+/// # Errors
+///
+/// Returns a message if `value` is not a CSS3 color name.
+pub fn parse_color(value: &str) -> Result<String, String> {
+    let value = value.trim().to_ascii_lowercase();
+    if CSS3_COLORS.binary_search(&value.as_str()).is_ok() {
+        Ok(value)
+    } else {
+        Err(format!(
+            "Invalid color '{value}', expected a CSS3 color name such as turquoise"
+        ))
+    }
+}
+
+/// Transliterate Norwegian letters (`æ`, `ø`, `å`) and drop any other
+/// non-ASCII characters, such as emoji.
+#[must_use]
+pub fn to_ascii(value: &str) -> String {
+    let mut ascii = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            'æ' => ascii.push_str("ae"),
+            'ø' => ascii.push_str("oe"),
+            'å' => ascii.push_str("aa"),
+            'Æ' => ascii.push_str("Ae"),
+            'Ø' => ascii.push_str("Oe"),
+            'Å' => ascii.push_str("Aa"),
+            c if c.is_ascii() => ascii.push(c),
+            _ => {}
+        }
+    }
+    ascii.trim().to_string()
+}
+
+/// Format `rrule` with its parts in the order of RFC 5545 and the values
+/// of each part sorted, so equivalent rules give identical output.
+#[must_use]
+pub fn canonical_rrule(rrule: &rrule::RRule) -> String {
+    canonical_parts(&rrule.to_string())
+}
+
+/// Sort the `;`-separated parts of a formatted rule. `FREQ` always comes
+/// first and parts not in RFC 5545 come last, in their original order.
+fn canonical_parts(formatted: &str) -> String {
+    const ORDER: [&str; 14] = [
+        "FREQ",
+        "UNTIL",
+        "COUNT",
+        "INTERVAL",
+        "BYSECOND",
+        "BYMINUTE",
+        "BYHOUR",
+        "BYDAY",
+        "BYMONTHDAY",
+        "BYYEARDAY",
+        "BYWEEKNO",
+        "BYMONTH",
+        "BYSETPOS",
+        "WKST",
+    ];
+    const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+    let mut parts: Vec<(&str, String)> = formatted
+        .split(';')
+        .filter_map(|part| part.split_once('='))
+        .map(|(name, value)| {
+            let mut values: Vec<&str> = value.split(',').collect();
+            // Numbers sort numerically and weekdays by day, then by offset
+            values.sort_by_key(|value| {
+                let (offset, day) = value.split_at(value.len().saturating_sub(2));
+                WEEKDAYS
+                    .iter()
+                    .position(|weekday| *weekday == day)
+                    .map_or_else(
+                        || (0, value.parse::<i32>().unwrap_or_default()),
+                        |day| (day, offset.parse::<i32>().unwrap_or_default()),
+                    )
+            });
+            values.dedup();
+            (name, values.join(","))
+        })
+        .collect();
+    parts.sort_by_key(|(name, _)| ORDER.iter().position(|n| n == name).unwrap_or(ORDER.len()));
+    parts
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// `rrule` with a date-time `UNTIL` cut to its date. RFC 5545 requires
+/// `UNTIL` to have the value type of `DTSTART`, which is always a DATE
+/// here. Occurrences are at midnight in [`RRULE_TIMEZONE`], so the date
+/// of `UNTIL` in that zone keeps the same occurrences.
+fn date_until(rrule: &str) -> String {
+    rrule
+        .split(';')
+        .map(|part| {
+            part.strip_prefix("UNTIL=")
+                .and_then(until_date)
+                .map_or_else(
+                    || part.to_string(),
+                    |date| format!("UNTIL={}", date.format("%Y%m%d")),
+                )
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The date of an `UNTIL` value in [`RRULE_TIMEZONE`]. A UTC date-time may
+/// be on the next day there.
+fn until_date(until: &str) -> Option<NaiveDate> {
+    until.strip_suffix('Z').map_or_else(
+        || {
+            until
+                .get(..8)
+                .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+        },
+        |utc| {
+            NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S")
+                .ok()
+                .map(|time| time.and_utc().with_timezone(&RRULE_TIMEZONE).date_naive())
+        },
+    )
+}
+
+/// `prop` with the address of its `mailto:` value as `CN`.
+fn mailto_property<'a>(mut prop: Property<'a>, mailto: &'a str) -> Property<'a> {
+    let name = mailto.strip_prefix("mailto:").unwrap_or(mailto);
+    prop.append(ics::parameters!("CN" => name));
+    prop
+}
+
 #[inline]
 fn format_timestamp<'a>(timestamp: &DateTime<Utc>) -> DelayedFormat<StrftimeItems<'a>> {
     timestamp.format("%Y%m%dT%H%M%SZ")
 }
 
+/// Format `duration` as an RFC 5545 `DURATION` value, like `P1D` or
+/// `PT1H30M`. Negative durations are treated as zero.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.num_seconds().max(0);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    let (hours, minutes, secs) = (secs / 3600, secs / 60 % 60, secs % 60);
+    let mut time = String::new();
+    for (n, unit) in [(hours, 'H'), (minutes, 'M'), (secs, 'S')] {
+        if n > 0 {
+            let _ = fmt::Write::write_fmt(&mut time, format_args!("{n}{unit}"));
+        }
+    }
+    match (days, time.is_empty()) {
+        (0, true) => "PT0S".to_string(),
+        (0, false) => format!("PT{time}"),
+        (_, true) => format!("P{days}D"),
+        (_, false) => format!("P{days}DT{time}"),
+    }
+}
+
+/// Time zone used when expanding RRULEs, so results don't depend on the
+/// host.
+pub const RRULE_TIMEZONE: rrule::Tz = rrule::Tz::Europe__Oslo;
+
+/// The first instant of `date` in `tz`, or midnight UTC if the day does not
+/// start at midnight because of a DST transition.
+#[must_use]
+pub fn start_of_day(date: NaiveDate, tz: rrule::Tz) -> DateTime<rrule::Tz> {
+    let midnight = date.and_time(NaiveTime::MIN);
+    midnight
+        .and_local_timezone(tz)
+        .earliest()
+        .unwrap_or_else(|| midnight.and_utc().with_timezone(&rrule::Tz::UTC))
+}
+
 #[inline]
 fn format_uid(uid: uuid::Uuid) -> String {
     let mut buf = Uuid::encode_buffer();
@@ -146,9 +1435,8 @@ fn format_uid(uid: uuid::Uuid) -> String {
 mod test {
     use super::*;
 
-    #[test]
-    fn test_calendar_to_string() {
-        let cal = Calendar {
+    fn calendar() -> Calendar {
+        Calendar {
             prodid: "-// Cal test //".to_string(),
             name: Some("Name".to_string()),
             description: Some("Description".to_string()),
@@ -166,11 +1454,795 @@ mod test {
                 sequence: Default::default(),
                 description: Default::default(),
                 recurrence_id: Default::default(),
+                this_and_future: false,
+                geo: None,
+                alarm: None,
+                extensions: Vec::new(),
             }],
-        };
+            options: RenderOptions::default(),
+            attendees: Vec::new(),
+            organizer: None,
+            color: None,
+            refresh_interval: None,
+            calscale: Some(DEFAULT_CALSCALE.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_calendar_to_string() {
+        let cal = calendar();
         assert_eq!(
             cal.to_string(),
             "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-// Cal test //\r\nCALSCALE:GREGORIAN\r\nMETHOD:PUBLISH\r\nNAME:Name\r\nX-WR-CALNAME:Name\r\nDESCRIPTION:Description\r\nX-WR-CALDESC:Description\r\nBEGIN:VEVENT\r\nUID:00000000-0000-0000-0000-000000000000\r\nDTSTAMP:19700101T000000Z\r\nSEQUENCE:0\r\nDTSTART;VALUE=DATE:20000203\r\nDTEND;VALUE=DATE:20000204\r\nSUMMARY:Summa summarum\\, hei\\; altså A☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}\r\n ☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}\r\nTRANSP:TRANSPARENT\r\nURL:http://example.com/\r\nEND:VEVENT\r\nEND:VCALENDAR\r\n"
         );
     }
+
+    #[test]
+    fn test_compact() {
+        let mut cal = calendar();
+        cal.events[0].description = Some("Beskrivelse".to_string());
+        cal.events[0].geo = Some((63.7, 11.3));
+        cal.events[0].alarm = Some(Alarm::new(Duration::days(1), AlarmAction::Display));
+        cal.events[0]
+            .extensions
+            .push(("X-TEST".to_string(), "1".to_string()));
+        let full = cal.to_string();
+        cal.options.compact = true;
+        let compact = cal.to_string();
+        assert!(compact.len() < full.len());
+        assert_eq!(check_rendered(&compact), Ok(1));
+        for name in ["TRANSP", "URL", "GEO", "DESCRIPTION", "X-", "BEGIN:VALARM"] {
+            assert!(full.contains(&format!("\r\n{name}")), "{name}");
+            assert!(!compact.contains(&format!("\r\n{name}")), "{name}");
+        }
+        for name in ["UID", "DTSTAMP", "DTSTART", "DTEND", "SUMMARY", "NAME"] {
+            assert!(compact.contains(&format!("\r\n{name}")), "{name}");
+        }
+    }
+
+    #[test]
+    fn test_omit() {
+        let mut cal = calendar();
+        cal.events[0].description = Some("Beskrivelse".to_string());
+        cal.events[0].alarm = Some(Alarm::new(Duration::days(1), AlarmAction::Audio));
+        let full = cal.to_string();
+        cal.options.omit = [OmitProperty::Transp, OmitProperty::Url]
+            .into_iter()
+            .collect();
+        let output = cal.to_string();
+        assert_eq!(check_rendered(&output), Ok(1));
+        for name in ["TRANSP", "URL"] {
+            assert!(full.contains(&format!("\r\n{name}:")), "{name}");
+            assert!(!output.contains(&format!("\r\n{name}:")), "{name}");
+        }
+        for name in [
+            "METHOD",
+            "CALSCALE",
+            "DESCRIPTION",
+            "BEGIN:VALARM",
+            "X-WR-CALNAME",
+        ] {
+            assert!(output.contains(&format!("\r\n{name}")), "{name}");
+        }
+
+        cal.events[0].geo = Some((63.7, 11.3));
+        cal.options.omit = OmitProperty::ALL.into_iter().collect();
+        let output = cal.to_string();
+        for name in ["METHOD", "CALSCALE", "DESCRIPTION", "VALARM", "GEO", "X-"] {
+            assert!(!output.contains(name), "{name}");
+        }
+        assert_eq!(check_rendered(&output), Ok(1));
+    }
+
+    #[test]
+    fn test_strict_rfc_omits_extensions() {
+        let mut cal = calendar();
+        cal.events[0]
+            .extensions
+            .push(("X-FOO".to_string(), "bar".to_string()));
+        assert!(cal.to_string().contains("X-FOO:bar\r\n"));
+        cal.options.strict_rfc = true;
+        let output = cal.to_string();
+        assert!(output.contains("NAME:Name\r\n"));
+        assert!(!output.lines().any(|line| line.starts_with("X-")));
+    }
+
+    #[test]
+    fn test_color_and_refresh_interval() {
+        let mut cal = calendar();
+        cal.color = Some("turquoise".to_string());
+        cal.refresh_interval = Some(Duration::hours(12));
+        let output = cal.to_string();
+        assert!(output.contains("\r\nCOLOR:turquoise\r\n"));
+        assert!(output.contains("\r\nREFRESH-INTERVAL;VALUE=DURATION:PT12H\r\n"));
+        assert!(output.contains("\r\nX-PUBLISHED-TTL:PT12H\r\n"));
+        cal.color = Some("red;x=1".to_string());
+        assert!(!cal.to_string().contains("COLOR"));
+        assert_eq!(parse_color(" Turquoise "), Ok("turquoise".to_string()));
+        assert!(parse_color("#40e0d0").is_err());
+        assert!(parse_color("red,blue").is_err());
+    }
+
+    #[test]
+    fn test_calscale() {
+        let mut cal = calendar();
+        cal.calscale = Some("JULIAN".to_string());
+        assert!(cal.to_string().contains("\r\nCALSCALE:JULIAN\r\n"));
+        cal.calscale = None;
+        assert!(!cal.to_string().contains("CALSCALE"));
+        assert_eq!(parse_calscale(" gregorian ").unwrap(), "GREGORIAN");
+        assert!(parse_calscale("").is_err());
+        assert!(parse_calscale("A:B").is_err());
+    }
+
+    #[test]
+    fn test_sorted_unique_dates() {
+        let mut cal = calendar();
+        let date = |day| NaiveDate::from_ymd_opt(2000, 3, day).unwrap();
+        cal.events[0].exdates = vec![date(9), date(2), date(9), date(5), date(2)];
+        cal.events[0].rdates = vec![date(20), date(10), date(20)];
+        let output = cal.to_string();
+        let lines = |name| {
+            output
+                .lines()
+                .filter(|line| line.starts_with(name))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            lines("EXDATE"),
+            [
+                "EXDATE;VALUE=DATE:20000302",
+                "EXDATE;VALUE=DATE:20000305",
+                "EXDATE;VALUE=DATE:20000309",
+            ]
+        );
+        assert_eq!(
+            lines("RDATE"),
+            ["RDATE;VALUE=DATE:20000310", "RDATE;VALUE=DATE:20000320"]
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut cal = calendar();
+        cal.events[0].description = Some("x".repeat(100));
+        let ics = cal.to_string();
+        let explained = explain(&ics);
+        assert!(explained.lines().all(|line| line.starts_with('#')));
+        assert!(explained.contains("\n# BEGIN:VCALENDAR\n"));
+        assert!(explained.contains("\n#   BEGIN:VEVENT\n"));
+        assert!(explained.contains(&format!("\n#     DESCRIPTION:{}\n", "x".repeat(100))));
+        assert!(explained.ends_with("\n# END:VCALENDAR\n"));
+        assert!(!explained.contains('\r'));
+        assert!(check_rendered(&explained).is_err());
+    }
+
+    #[test]
+    fn test_is_valid() {
+        // The fixture event has a nil UID
+        let valid = || {
+            let mut cal = calendar();
+            cal.events[0].uid = Uuid::from_u128(1);
+            cal
+        };
+        let problems = |edit: fn(&mut Event)| {
+            let mut cal = valid();
+            edit(&mut cal.events[0]);
+            cal.is_valid().unwrap_err()
+        };
+        assert_eq!(valid().is_valid(), Ok(()));
+
+        let uid = problems(|e| e.uid = Uuid::nil());
+        assert!(
+            matches!(uid.as_slice(), [p] if p.ends_with("UID is empty")),
+            "{uid:?}"
+        );
+
+        let summary = problems(|e| e.summary = " ".to_string());
+        assert!(
+            matches!(summary.as_slice(), [p] if p.ends_with("SUMMARY is empty")),
+            "{summary:?}"
+        );
+
+        let dtend = problems(|e| e.date = NaiveDate::MAX);
+        assert!(
+            matches!(dtend.as_slice(), [p] if p.ends_with("DTEND is not after DTSTART")),
+            "{dtend:?}"
+        );
+
+        let dates = problems(|e| {
+            e.exdates = vec![e.date - Duration::days(1), e.date];
+            e.rdates = vec![e.date + Months::new(12 * (MAX_RECURRENCE_YEARS + 1))];
+        });
+        assert_eq!(dates.len(), 2, "{dates:?}");
+        assert!(dates[0].contains(": RDATE "), "{dates:?}");
+        assert!(dates[1].contains(": EXDATE "), "{dates:?}");
+
+        let mut cal = valid();
+        cal.events.push(cal.events[0].clone());
+        cal.events[1].summary = String::new();
+        cal.events[0].uid = Uuid::nil();
+        assert_eq!(cal.is_valid().unwrap_err().len(), 2);
+    }
+
+    #[test]
+    fn test_fold_keeps_zwj_sequences() {
+        let family = "👨\u{200d}👩\u{200d}👧\u{200d}👦";
+        let mut cal = calendar();
+        cal.events[0].summary = format!("Familie {}", family.repeat(4));
+        let output = cal.to_string();
+        let lines: Vec<_> = output.split("\r\n").collect();
+        for (line, next) in lines.iter().zip(&lines[1..]) {
+            assert!(line.len() <= LINE_LIMIT, "{line}");
+            if let Some(next) = next.strip_prefix(' ') {
+                assert!(!line.ends_with('\u{200d}'), "{line}");
+                assert!(!next.starts_with(is_extending), "{next}");
+                assert!(next.starts_with(family), "{next}");
+            }
+        }
+        assert!(
+            output
+                .replace("\r\n ", "")
+                .contains(&format!("\r\nSUMMARY:Familie {}\r\n", family.repeat(4)))
+        );
+        // A sequence longer than a line is split anyway
+        let long = format!("a{}", "\u{0301}".repeat(50));
+        assert_eq!(fold_boundary(&long, LINE_LIMIT), 75);
+        assert_eq!(fold_boundary(&long, LINE_LIMIT - 1), 73);
+    }
+
+    #[test]
+    fn test_stamp_version() {
+        let mut cal = calendar();
+        cal.prodid = "-//Aasan//Aasan Postgang//EN".to_string();
+        cal.stamp_version("git:v1.2");
+        assert_eq!(cal.prodid, "-//Aasan//Aasan Postgang git:v1.2//EN");
+        cal.prodid = "Postgang".to_string();
+        cal.stamp_version("1.2");
+        assert_eq!(cal.prodid, "Postgang 1.2");
+    }
+
+    #[test]
+    fn test_decorate_summaries() {
+        let mut cal = calendar();
+        cal.events[0].summary = "Post".to_string();
+        cal.decorate_summaries("[Hjem] ", "; ok");
+        assert_eq!(cal.events[0].summary, "[Hjem] Post; ok");
+        assert!(
+            cal.to_string()
+                .contains("\r\nSUMMARY:[Hjem] Post\\; ok\r\n")
+        );
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Duration::days(1)), "P1D");
+        assert_eq!(format_duration(Duration::minutes(90)), "PT1H30M");
+        assert_eq!(format_duration(Duration::seconds(86_401)), "P1DT1S");
+        assert_eq!(format_duration(Duration::zero()), "PT0S");
+    }
+
+    #[test]
+    fn test_parse_flexible_date() {
+        let expected = NaiveDate::from_ymd_opt(2024, 3, 1);
+        assert_eq!(parse_flexible_date("2024-03-01").ok(), expected);
+        assert_eq!(parse_flexible_date("01.03.2024").ok(), expected);
+        assert_eq!(parse_flexible_date("20240301").ok(), expected);
+        assert!(parse_flexible_date("2024-02-30").is_err());
+        assert!(parse_flexible_date("03/01/2024").is_err());
+    }
+
+    #[test]
+    fn test_retain_weekdays() {
+        let mut cal = calendar();
+        let event = cal.events[0].clone();
+        // Friday to Monday
+        cal.events = ["2024-03-01", "2024-03-02", "2024-03-03", "2024-03-04"]
+            .map(|value| Event {
+                date: date(value),
+                ..event.clone()
+            })
+            .to_vec();
+        let dates = |only: &[Weekday], skip_weekends| {
+            let mut cal = cal.clone();
+            cal.retain_weekdays(only, skip_weekends);
+            cal.events.iter().map(|e| e.date).collect::<Vec<_>>()
+        };
+        assert_eq!(dates(&[], false).len(), 4);
+        assert_eq!(dates(&[], true), ["2024-03-01", "2024-03-04"].map(date));
+        assert_eq!(
+            dates(&[Weekday::Sat, Weekday::Mon], false),
+            ["2024-03-02", "2024-03-04"].map(date)
+        );
+        assert_eq!(
+            dates(&[Weekday::Sat, Weekday::Mon], true),
+            [date("2024-03-04")]
+        );
+        assert_eq!(parse_weekday("mon"), Ok(Weekday::Mon));
+        assert_eq!(parse_weekday("Saturday"), Ok(Weekday::Sat));
+        assert!(parse_weekday("lørdag").is_err());
+    }
+
+    #[test]
+    fn test_to_ical_allows_extra_properties() {
+        let cal = calendar();
+        let mut ical = cal.to_ical();
+        ical.push(Property::new("X-FOO", "bar"));
+        let mut buf = Vec::new();
+        ical.write(&mut buf).unwrap();
+        let output = String::from_utf8(buf).unwrap();
+        assert!(output.contains("X-FOO:bar\r\nBEGIN:VEVENT"));
+    }
+
+    #[test]
+    fn test_events_only() {
+        let mut cal = calendar();
+        cal.options.events_only = true;
+        let output = cal.to_string();
+        assert!(output.starts_with("BEGIN:VEVENT\r\n"));
+        assert!(output.ends_with("END:VEVENT\r\n"));
+        assert!(!output.contains("VCALENDAR"));
+        assert!(!output.contains("PRODID"));
+    }
+
+    #[test]
+    fn test_no_dtend() {
+        let mut cal = calendar();
+        assert!(cal.to_string().contains("\r\nDTEND;VALUE=DATE:"));
+        cal.options.no_dtend = true;
+        let output = cal.to_string();
+        assert!(output.contains("\r\nDTSTART;VALUE=DATE:"));
+        assert!(!output.contains("DTEND"));
+        cal.options.events_only = true;
+        assert!(!cal.to_string().contains("DTEND"));
+        cal.events[0].duration = NonZeroU8::new(2).unwrap();
+        assert!(cal.to_string().contains("\r\nDTEND;VALUE=DATE:"));
+    }
+
+    #[test]
+    fn test_ascii() {
+        let mut cal = calendar();
+        cal.events[0].summary = "🍌 Matavfall lørdag 3.".to_string();
+        cal.name = Some("Tømming".to_string());
+        cal.description = Some("Søppel på Ås".to_string());
+        cal.options.ascii = true;
+        let output = cal.to_string();
+        assert!(output.contains("\r\nSUMMARY:Matavfall loerdag 3.\r\n"));
+        assert!(output.contains("\r\nNAME:Toemming\r\nX-WR-CALNAME:Toemming\r\n"));
+        assert!(output.contains("\r\nX-WR-CALDESC:Soeppel paa Aas\r\n"));
+        assert!(output.is_ascii());
+        assert_eq!(to_ascii("Søppeltømming på Ås"), "Soeppeltoemming paa Aas");
+    }
+
+    #[test]
+    fn test_max_summary_len() {
+        let mut cal = calendar();
+        cal.options.max_summary_len = NonZeroUsize::new(20);
+        assert!(
+            cal.to_string()
+                .contains("\r\nSUMMARY:Summa summarum\\, hei…\r\n")
+        );
+
+        cal.options.max_summary_len = NonZeroUsize::new(42);
+        let output = cal.to_string();
+        assert!(output.contains(
+            "\r\nSUMMARY:Summa summarum\\, hei\\; altså A☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}☣\u{fe0f}\r\n …\r\n"
+        ));
+        assert!(output.split("\r\n").all(|line| line.len() <= 75));
+
+        cal.options.max_summary_len = NonZeroUsize::new(1000);
+        assert_eq!(cal.to_string(), calendar().to_string());
+
+        let options = RenderOptions {
+            max_summary_len: NonZeroUsize::new(4),
+            ..RenderOptions::default()
+        };
+        assert_eq!(options.summary("ab👩\u{200D}👧"), "ab…");
+    }
+
+    fn rrule(value: &str, dt_start: NaiveDate) -> rrule::RRule {
+        let dt_start = dt_start.and_time(NaiveTime::MIN).and_utc();
+        value
+            .parse::<rrule::RRule<rrule::Unvalidated>>()
+            .unwrap()
+            .validate(dt_start.with_timezone(&rrule::Tz::UTC))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_canonical_rrule() {
+        let dt_start = date("2024-01-01");
+        let a = rrule("FREQ=WEEKLY;BYDAY=WE,MO;INTERVAL=2", dt_start);
+        let b = rrule("INTERVAL=2;BYDAY=MO,WE;FREQ=WEEKLY", dt_start);
+        assert_eq!(
+            canonical_rrule(&a),
+            "FREQ=WEEKLY;INTERVAL=2;BYSECOND=0;BYMINUTE=0;BYHOUR=0;BYDAY=MO,WE"
+        );
+        assert_eq!(canonical_rrule(&a), canonical_rrule(&b));
+
+        let a = rrule("FREQ=MONTHLY;BYMONTHDAY=15,1;BYMONTH=12,3", dt_start);
+        assert_eq!(
+            canonical_rrule(&a),
+            "FREQ=MONTHLY;BYSECOND=0;BYMINUTE=0;BYHOUR=0;BYMONTHDAY=1,15;BYMONTH=3,12"
+        );
+        let a = rrule("FREQ=MONTHLY;BYDAY=FR,-1MO,2MO", dt_start);
+        assert!(canonical_rrule(&a).ends_with(";BYDAY=-1MO,2MO,FR"));
+
+        assert_eq!(
+            canonical_parts("X-B=1;BYDAY=MO;X-A=2;FREQ=WEEKLY"),
+            "FREQ=WEEKLY;BYDAY=MO;X-B=1;X-A=2"
+        );
+    }
+
+    fn date(value: &str) -> NaiveDate {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_occurrences() {
+        let mut event = calendar().events.remove(0);
+        event.date = date("2024-01-01");
+        event.rrule = Some(rrule("FREQ=WEEKLY", event.date));
+        event.exdates = vec![date("2024-01-08")];
+        event.rdates = vec![date("2024-01-10")];
+        assert_eq!(
+            event.occurrences(4),
+            ["2024-01-01", "2024-01-10", "2024-01-15", "2024-01-22"].map(date)
+        );
+        assert_eq!(event.occurrences(100).len(), 100);
+        assert!(event.occurrences(0).is_empty());
+    }
+
+    #[test]
+    fn test_expand_recurring() {
+        let mut cal = calendar();
+        let single = cal.events[0].clone();
+        let mut master = single.clone();
+        master.uid = Uuid::now_v7();
+        master.date = date("2024-01-01");
+        master.rrule = Some(rrule("FREQ=WEEKLY", master.date));
+        master.exdates = vec![date("2024-01-08"), date("2024-01-15")];
+        let mut moved = master.clone();
+        moved.rrule = None;
+        moved.exdates = Vec::new();
+        moved.recurrence_id = Some(date("2024-01-15"));
+        moved.date = date("2024-01-16");
+        cal.events = vec![single.clone(), master.clone(), moved];
+
+        cal.expand_recurring(3);
+        let dates: Vec<_> = cal.events.iter().map(|e| e.date).collect();
+        assert_eq!(
+            dates,
+            [
+                "2000-02-03",
+                "2024-01-01",
+                "2024-01-22",
+                "2024-01-29",
+                "2024-01-16"
+            ]
+            .map(date)
+        );
+        assert_eq!(cal.events[0].uid, single.uid);
+        let uids: std::collections::HashSet<_> = cal.events.iter().map(|e| e.uid).collect();
+        assert_eq!(uids.len(), 5);
+        assert!(!uids.contains(&master.uid));
+        assert!(cal.events.iter().all(|e| e.rrule.is_none()));
+        assert!(cal.events.iter().all(|e| e.recurrence_id.is_none()));
+        assert!(cal.events.iter().all(|e| e.exdates.is_empty()));
+
+        // The overridden date is left out even without an EXDATE
+        master.exdates = Vec::new();
+        let mut moved = master.clone();
+        moved.rrule = None;
+        moved.recurrence_id = Some(date("2024-01-08"));
+        moved.date = date("2024-01-09");
+        cal.events = vec![master, moved];
+        cal.expand_recurring(3);
+        let dates: Vec<_> = cal.events.iter().map(|e| e.date).collect();
+        assert_eq!(
+            dates,
+            ["2024-01-01", "2024-01-15", "2024-01-22", "2024-01-09"].map(date)
+        );
+        let uids: std::collections::HashSet<_> = cal.events.iter().map(|e| e.uid).collect();
+        assert_eq!(uids.len(), 4);
+    }
+
+    #[test]
+    fn test_calendar_occurrences() {
+        let mut cal = calendar();
+        let mut master = cal.events.remove(0);
+        master.date = date("2024-01-01");
+        master.rrule = Some(rrule("FREQ=WEEKLY", master.date));
+        let mut moved = master.clone();
+        moved.rrule = None;
+        moved.recurrence_id = Some(date("2024-01-08"));
+        moved.date = date("2024-01-10");
+        assert_eq!(moved.occurrences(3), [date("2024-01-10")]);
+        cal.events = vec![master.clone(), moved];
+        assert_eq!(
+            cal.occurrences(master.uid, 3),
+            ["2024-01-01", "2024-01-10", "2024-01-15"].map(date)
+        );
+        assert!(cal.occurrences(Uuid::max(), 3).is_empty());
+    }
+
+    #[test]
+    fn test_start_of_day() {
+        // Norway changes to summer time at 02:00
+        let start = start_of_day(date("2024-03-31"), RRULE_TIMEZONE);
+        assert_eq!(start.to_rfc3339(), "2024-03-31T00:00:00+01:00");
+        let start = start_of_day(date("2024-04-01"), RRULE_TIMEZONE);
+        assert_eq!(start.to_rfc3339(), "2024-04-01T00:00:00+02:00");
+        // Chile skips from 24:00 to 01:00
+        let start = start_of_day(date("2024-09-08"), rrule::Tz::America__Santiago);
+        assert_eq!(start.to_rfc3339(), "2024-09-08T00:00:00+00:00");
+    }
+
+    #[test]
+    fn test_event_builder() {
+        let uid = uuid::uuid!("00000000-0000-0000-0000-000000000001");
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let dtstamp = DateTime::from_timestamp(0, 0).unwrap();
+        let built = Event::builder(uid, date, "Post", dtstamp)
+            .sequence(3)
+            .description(Some("Hei".to_string()))
+            .recurrence_id(date, true)
+            .extension("X-TEST", "1")
+            .build()
+            .unwrap();
+        let manual = Event {
+            uid,
+            dtstamp,
+            duration: NonZeroU8::MIN,
+            rrule: None,
+            rdates: Vec::new(),
+            exdates: Vec::new(),
+            sequence: 3,
+            date,
+            summary: "Post".to_string(),
+            description: Some("Hei".to_string()),
+            url: None,
+            recurrence_id: Some(date),
+            this_and_future: true,
+            geo: None,
+            alarm: None,
+            extensions: vec![("X-TEST".to_string(), "1".to_string())],
+        };
+        assert_eq!(built, manual);
+        assert!(Event::builder(uid, date, " ", dtstamp).build().is_err());
+        let invalid = Event::builder(uid, date, "Post", dtstamp).extension("TEST", "1");
+        assert!(invalid.build().is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut cal = calendar();
+        cal.name = None;
+        let first = cal.events[0].clone();
+        let mut other = calendar();
+        other.prodid = "-// Other //".to_string();
+        other.name = Some("Other".to_string());
+        other.description = Some("Other description".to_string());
+        let mut newer = first.clone();
+        newer.sequence = 2;
+        newer.summary = "Newer".to_string();
+        let mut moved = first.clone();
+        moved.recurrence_id = Some(first.date);
+        let mut unrelated = first.clone();
+        unrelated.uid = Uuid::now_v7();
+        other.events = vec![newer, moved, unrelated.clone()];
+        let mut older = calendar();
+        let mut stale = first.clone();
+        stale.sequence = -1;
+        stale.summary = "Older".to_string();
+        older.events = vec![stale];
+
+        cal.merge(other);
+        cal.merge(older);
+        assert_eq!(cal.prodid, "-// Cal test //");
+        assert_eq!(cal.name.as_deref(), Some("Other"));
+        assert_eq!(cal.description.as_deref(), Some("Description"));
+        let events: Vec<_> = cal
+            .events
+            .iter()
+            .map(|e| (e.uid, e.recurrence_id, e.summary.as_str()))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (first.uid, None, "Newer"),
+                (first.uid, Some(first.date), first.summary.as_str()),
+                (unrelated.uid, None, first.summary.as_str()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_flatten_multiday() {
+        let mut cal = calendar();
+        let single = cal.events[0].clone();
+        let mut multiday = single.clone();
+        multiday.uid = Uuid::now_v7();
+        multiday.duration = NonZeroU8::new(3).unwrap();
+        cal.events.push(multiday.clone());
+
+        cal.flatten_multiday();
+        assert_eq!(cal.events.len(), 4);
+        assert_eq!(cal.events[0].uid, single.uid);
+        let days = &cal.events[1..];
+        assert_eq!(
+            days.iter().map(|e| e.date).collect::<Vec<_>>(),
+            ["2000-02-03", "2000-02-04", "2000-02-05"].map(date)
+        );
+        assert!(days.iter().all(|e| e.duration == NonZeroU8::MIN));
+        let uids: std::collections::HashSet<_> = cal.events.iter().map(|e| e.uid).collect();
+        assert_eq!(uids.len(), 4);
+        assert!(!uids.contains(&multiday.uid));
+
+        let mut cal = calendar();
+        let mut moved = cal.events[0].clone();
+        moved.recurrence_id = Some(moved.date);
+        moved.duration = NonZeroU8::new(2).unwrap();
+        cal.events = vec![moved.clone()];
+        cal.flatten_multiday();
+        assert_eq!(cal.events, [moved]);
+    }
+
+    #[test]
+    fn test_wrap_description() {
+        let mut cal = calendar();
+        cal.events[0].description =
+            Some("Husk å sette ut dunken kvelden før tømming.\nGjelder hele veien.".to_string());
+        cal.options.wrap_description = NonZeroUsize::new(20);
+        let unfolded = cal.to_string().replace("\r\n ", "");
+        assert!(unfolded.contains(
+            "\r\nDESCRIPTION:Husk å sette ut\\ndunken kvelden før\\ntømming.\\nGjelder hele veien.\r\n"
+        ));
+        assert_eq!(word_wrap("a  b", 10), "a  b");
+        assert_eq!(word_wrap("aaaaaa bbbbbb", 3), "aaaaaa\nbbbbbb");
+    }
+
+    #[test]
+    fn test_date_until() {
+        assert_eq!(
+            date_until("FREQ=WEEKLY;UNTIL=20240114T225959Z;BYDAY=MO"),
+            "FREQ=WEEKLY;UNTIL=20240114;BYDAY=MO"
+        );
+        // Late in the evening UTC is already the next day in Oslo
+        assert_eq!(
+            date_until("FREQ=DAILY;UNTIL=20260210T230000Z"),
+            "FREQ=DAILY;UNTIL=20260211"
+        );
+        assert_eq!(
+            date_until("FREQ=DAILY;UNTIL=20260710T215959Z"),
+            "FREQ=DAILY;UNTIL=20260710"
+        );
+        assert_eq!(
+            date_until("FREQ=DAILY;UNTIL=20240114"),
+            "FREQ=DAILY;UNTIL=20240114"
+        );
+        assert_eq!(date_until("FREQ=DAILY;COUNT=3"), "FREQ=DAILY;COUNT=3");
+    }
+
+    #[test]
+    fn test_ending_before() {
+        let mut weekly = calendar().events[0].clone();
+        weekly.date = date("2024-01-01");
+        weekly.rrule = Some(rrule("FREQ=WEEKLY", weekly.date));
+        weekly.exdates = vec![date("2024-01-08"), date("2024-01-22")];
+        weekly.rdates = vec![date("2024-01-03"), date("2024-01-31")];
+        let event = weekly.clone().ending_before(date("2024-01-15")).unwrap();
+        assert_eq!(
+            event.occurrences(10),
+            ["2024-01-01", "2024-01-03"].map(date)
+        );
+        assert_eq!(event.exdates, [date("2024-01-08")]);
+        let ics = event.to_ics(RenderOptions::default(), None).to_string();
+        assert!(
+            ics.contains("\r\nRRULE:FREQ=WEEKLY;UNTIL=20240114;"),
+            "{ics}"
+        );
+
+        let mut counted = weekly.clone();
+        counted.rrule = Some(rrule("FREQ=WEEKLY;COUNT=5", counted.date));
+        let event = counted.ending_before(date("2024-01-16")).unwrap();
+        assert_eq!(event.rrule.unwrap().get_count(), Some(3));
+
+        assert!(weekly.ending_before(date("2024-01-01")).is_none());
+    }
+
+    #[test]
+    fn test_this_and_future() {
+        let mut cal = calendar();
+        cal.events[0].recurrence_id = Some(date("2000-02-01"));
+        assert!(
+            cal.to_string()
+                .contains("\r\nRECURRENCE-ID;VALUE=DATE:20000201\r\n")
+        );
+        cal.events[0].this_and_future = true;
+        assert!(
+            cal.to_string()
+                .contains("\r\nRECURRENCE-ID;RANGE=THISANDFUTURE;VALUE=DATE:20000201\r\n")
+        );
+    }
+
+    #[test]
+    fn test_check_selftest() {
+        let ics = calendar().to_string();
+        assert_eq!(
+            check_selftest(core::slice::from_ref(&ics), 1),
+            Ok(ics.clone())
+        );
+        assert_eq!(
+            check_selftest(&[ics], 2),
+            Err("Expected 2 events, found 1".to_string())
+        );
+    }
+
+    #[test]
+    fn test_check_rendered() {
+        let mut cal = calendar();
+        assert_eq!(check_rendered(&cal.to_string()), Ok(1));
+        cal.options.events_only = true;
+        assert!(check_rendered(&cal.to_string()).is_err());
+        cal.options.events_only = false;
+        cal.events.clear();
+        assert_eq!(
+            check_rendered(&cal.to_string()),
+            Err("No events".to_string())
+        );
+    }
+
+    #[test]
+    fn test_drop_before() {
+        let mut cal = calendar();
+        let old = cal.events[0].clone();
+        let event = |date_str: &str| Event {
+            uid: Uuid::now_v7(),
+            date: date(date_str),
+            ..old.clone()
+        };
+        let recent = event("2024-01-20");
+        let mut weekly = event("2024-01-01");
+        weekly.rrule = Some(rrule("FREQ=WEEKLY", weekly.date));
+        weekly.exdates = vec![date("2024-01-08"), date("2024-01-22")];
+        let mut counted = event("2024-01-02");
+        counted.rrule = Some(rrule("FREQ=WEEKLY;COUNT=4", counted.date));
+        let mut ended = event("2023-01-01");
+        ended.rrule = Some(rrule("FREQ=DAILY;COUNT=2", ended.date));
+        let mut rdates = ended.clone();
+        rdates.uid = Uuid::now_v7();
+        rdates.rdates = vec![date("2024-02-01"), date("2024-01-10")];
+        cal.events = vec![old, recent.clone(), weekly, counted, ended, rdates];
+
+        cal.drop_before(date("2024-01-15"));
+        let weekly_rrule = |day: &str, count: &str| {
+            format!("FREQ=WEEKLY{count};BYSECOND=0;BYMINUTE=0;BYHOUR=0;BYDAY={day}")
+        };
+        let events: Vec<_> = cal
+            .events
+            .iter()
+            .map(|e| (e.date, e.rrule.as_ref().map(canonical_rrule)))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                (recent.date, None),
+                (date("2024-01-15"), Some(weekly_rrule("MO", ""))),
+                (date("2024-01-16"), Some(weekly_rrule("TU", ";COUNT=2"))),
+                (date("2024-02-01"), None),
+            ]
+        );
+        assert_eq!(cal.events[1].exdates, [date("2024-01-22")]);
+        assert!(cal.events[3].rdates.is_empty());
+    }
+
+    #[test]
+    fn test_occurrences_without_rrule() {
+        let mut event = calendar().events.remove(0);
+        assert_eq!(event.occurrences(10), [date("2000-02-03")]);
+        event.rdates = vec![date("2000-01-01")];
+        event.exdates = vec![date("2000-02-03")];
+        assert_eq!(event.occurrences(10), [date("2000-01-01")]);
+    }
 }
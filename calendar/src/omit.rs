@@ -0,0 +1,116 @@
+//! Optional properties to leave out for picky clients.
+
+use core::fmt;
+
+/// An optional property, or group of properties, that can be left out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum OmitProperty {
+    /// `TRANSP` on events
+    Transp,
+    /// `METHOD` on the calendar
+    Method,
+    /// `URL` on events
+    Url,
+    /// `DESCRIPTION` on the calendar and events
+    Description,
+    /// `VALARM` reminders on events
+    Valarm,
+    /// `GEO` on events
+    Geo,
+    /// `CALSCALE` on the calendar
+    Calscale,
+    /// Non-standard `X-` properties
+    XProps,
+}
+
+impl OmitProperty {
+    /// Every property, in declaration order.
+    pub const ALL: [Self; 8] = [
+        Self::Transp,
+        Self::Method,
+        Self::Url,
+        Self::Description,
+        Self::Valarm,
+        Self::Geo,
+        Self::Calscale,
+        Self::XProps,
+    ];
+
+    const fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+/// Writes the name used on the command line.
+impl fmt::Display for OmitProperty {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Transp => "transp",
+            Self::Method => "method",
+            Self::Url => "url",
+            Self::Description => "description",
+            Self::Valarm => "valarm",
+            Self::Geo => "geo",
+            Self::Calscale => "calscale",
+            Self::XProps => "x-props",
+        })
+    }
+}
+
+/// A set of [`OmitProperty`], empty by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Omit(u8);
+
+impl Omit {
+    /// Whether `property` is left out.
+    #[must_use]
+    pub const fn contains(self, property: OmitProperty) -> bool {
+        self.0 & property.bit() != 0
+    }
+
+    /// Also leave out `property`.
+    #[must_use]
+    pub const fn with(self, property: OmitProperty) -> Self {
+        Self(self.0 | property.bit())
+    }
+}
+
+impl FromIterator<OmitProperty> for Omit {
+    fn from_iter<I: IntoIterator<Item = OmitProperty>>(iter: I) -> Self {
+        iter.into_iter().fold(Self::default(), Self::with)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_omit() {
+        let omit: Omit = [OmitProperty::Transp, OmitProperty::XProps]
+            .into_iter()
+            .collect();
+        for property in OmitProperty::ALL {
+            assert_eq!(
+                omit.contains(property),
+                matches!(property, OmitProperty::Transp | OmitProperty::XProps),
+                "{property}"
+            );
+        }
+        assert!(!Omit::default().contains(OmitProperty::Transp));
+        assert_eq!(OmitProperty::XProps.to_string(), "x-props");
+    }
+
+    #[cfg(feature = "clap")]
+    #[test]
+    fn test_value_names() {
+        use clap::ValueEnum;
+
+        for property in OmitProperty::ALL {
+            let value = property.to_possible_value().unwrap();
+            assert_eq!(value.get_name(), property.to_string());
+        }
+        assert_eq!(OmitProperty::value_variants(), OmitProperty::ALL);
+    }
+}
@@ -1,20 +1,10 @@
 use core::error::Error;
-use std::{env, ffi::OsString, process::ExitCode};
+use std::{ffi::OsString, process::ExitCode};
 
-use clap::{Parser as ClapParser, Subcommand, ValueEnum};
-use git_version::git_version;
+use clap::{Parser as ClapParser, Subcommand};
+use common::logging::{self, LogArgs, LogFormat};
 
-const VERSION: &str = git_version!(
-    prefix = "git:",
-    cargo_prefix = "cargo:",
-    fallback = "unknown"
-);
-
-#[derive(Debug, Clone, ValueEnum)]
-enum OutputFormat {
-    Ical,
-    Json,
-}
+const VERSION: &str = common::version!();
 
 #[derive(ClapParser, Debug)]
 #[clap(version = VERSION)]
@@ -28,94 +18,114 @@ struct Cli {
 enum Commands {
     #[command(external_subcommand)]
     Main(Vec<OsString>),
-    Install,
+    Install {
+        #[command(flatten)]
+        log: LogArgs,
+    },
     Garbage {
         #[command(subcommand)]
         command: garbage::Commands,
+        #[command(flatten)]
+        log: LogArgs,
     },
     Postgang {
         #[command(subcommand)]
         command: postgang::Commands,
+        #[command(flatten)]
+        log: LogArgs,
     },
     Calendar {
         #[command(subcommand)]
         command: calendar_db::Commands,
+        #[command(flatten)]
+        log: LogArgs,
+    },
+    /// Render calendars from bundled data to check that the tools work
+    /// offline
+    Selftest {
+        #[command(flatten)]
+        log: LogArgs,
     },
 }
 
-fn handle_cli(cli: Cli) -> Result<(), Box<dyn Error>> {
+impl Commands {
+    /// The `--log-format` of the tool.
+    fn log_format(&self) -> LogFormat {
+        match self {
+            Self::Main(_) => LogFormat::default(),
+            Self::Install { log }
+            | Self::Garbage { log, .. }
+            | Self::Postgang { log, .. }
+            | Self::Calendar { log, .. }
+            | Self::Selftest { log } => log.log_format,
+        }
+    }
+}
+
+/// The command line of the tool named by a `rizwold <tool> ...` call.
+fn resolve(cli: Cli) -> Cli {
     match cli.command {
-        Commands::Main(args) => handle_cli(Cli::parse_from(args.iter().skip(1))),
-        Commands::Install => {
+        Commands::Main(args) => resolve(Cli::parse_from(args.iter().skip(1))),
+        _ => cli,
+    }
+}
+
+fn handle_cli(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match resolve(cli).command {
+        Commands::Main(_) => unreachable!("Resolved above"),
+        Commands::Install { .. } => {
             eprintln!("Not yet implemented");
             Ok(())
         }
-        Commands::Garbage { command } => Ok(command.run()?),
-        Commands::Postgang { command } => Ok(command.run()?),
-        Commands::Calendar { command } => Ok(command.run()?),
+        Commands::Garbage { command, .. } => Ok(command.run()?),
+        Commands::Postgang { command, .. } => Ok(command.run()?),
+        Commands::Calendar { command, .. } => Ok(command.run()?),
+        Commands::Selftest { .. } => {
+            garbage::Commands::Selftest.run()?;
+            postgang::Commands::Selftest.run()?;
+            Ok(())
+        }
     }
 }
 
-fn try_main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+fn try_main(cli: Cli) -> Result<(), Box<dyn Error>> {
     tracing::debug!("Got CLI args: {cli:?}");
     handle_cli(cli)
 }
 
 fn main() -> ExitCode {
-    let _logger_guard = init_logging();
-    match try_main() {
+    let cli = resolve(Cli::parse());
+    let _logger_guard = logging::init(cli.command.log_format(), "garbage.log");
+    match try_main(cli) {
         Ok(()) => {
             tracing::info!("Success");
             ExitCode::SUCCESS
         }
         Err(err) => {
-            tracing::error!("{err}");
+            tracing::error!(error = %err, "Command failed");
             ExitCode::FAILURE
         }
     }
 }
 
-fn init_logging() -> tracing_appender::non_blocking::WorkerGuard {
-    use std::fs::create_dir_all;
-    use tracing_appender::rolling::{RollingFileAppender, Rotation};
-    use tracing_subscriber::{EnvFilter, fmt, prelude::*};
-
-    #[allow(clippy::disallowed_methods)]
-    if let Some(dir) = &env::var_os("RIZWOLD_LOG_DIR") {
-        if let Err(err) = create_dir_all(dir) {
-            eprintln!("Unable to initialize logging to file: {err}");
-        } else {
-            match RollingFileAppender::builder()
-                .rotation(Rotation::WEEKLY)
-                .max_log_files(8)
-                .filename_prefix("garbage.log")
-                .build(dir)
-            {
-                Err(err) => {
-                    eprintln!(
-                        "Unable to initialize logging in directory: {}",
-                        dir.display()
-                    );
-                    eprintln!("Cause: {err}");
-                }
-                Ok(file_appender) => {
-                    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+#[cfg(test)]
+mod test {
+    use super::*;
 
-                    tracing_subscriber::registry()
-                        .with(EnvFilter::from_default_env())
-                        .with(fmt::layer().with_writer(non_blocking).with_ansi(false))
-                        .init();
-                    return guard;
-                }
-            }
-        }
+    #[test]
+    fn test_selftest() {
+        let cli = Cli::try_parse_from(["rizwold", "selftest"]).unwrap();
+        assert!(handle_cli(cli).is_ok());
+        let cli = Cli::try_parse_from(["garbage", "selftest"]).unwrap();
+        assert!(handle_cli(cli).is_ok());
     }
-    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stderr());
-    tracing_subscriber::registry()
-        .with(EnvFilter::from_default_env())
-        .with(fmt::layer().with_writer(non_blocking).with_ansi(false))
-        .init();
 
-    guard
+    #[test]
+    fn test_log_format() {
+        let cli = Cli::try_parse_from(["garbage", "--log-format", "json", "selftest"]).unwrap();
+        assert_eq!(cli.command.log_format(), LogFormat::Json);
+        let cli = Cli::try_parse_from(["garbage", "selftest"]).unwrap();
+        assert_eq!(cli.command.log_format(), LogFormat::Text);
+        assert!(Cli::try_parse_from(["garbage", "--log-format", "xml", "selftest"]).is_err());
+    }
 }
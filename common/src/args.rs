@@ -0,0 +1,48 @@
+//! Value parsers for command line arguments.
+
+/// Accept an octal file mode such as `644` or `0600`.
+///
+/// # Errors
+///
+/// Fails if `value` is not octal or has bits above `0o7777`.
+pub fn parse_mode(value: &str) -> Result<u32, String> {
+    u32::from_str_radix(value, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("Invalid file mode '{value}', expected octal such as 0644"))
+}
+
+/// Parse an http(s) URL.
+///
+/// # Errors
+///
+/// Fails if `value` is not a URL, or its scheme is not `http` or `https`.
+pub fn parse_http_url(value: &str) -> Result<url::Url, String> {
+    let url = url::Url::parse(value).map_err(|err| err.to_string())?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err("scheme must be http or https".to_string());
+    }
+    Ok(url)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0600"), Ok(0o600));
+        assert!(parse_mode("0o644").is_err());
+        assert!(parse_mode("888").is_err());
+        assert!(parse_mode("17777").is_err());
+    }
+
+    #[test]
+    fn test_parse_http_url() {
+        assert!(parse_http_url("https://example.com/7800.json").is_ok());
+        assert!(parse_http_url("http://localhost:8080/").is_ok());
+        assert!(parse_http_url("file:///tmp/7800.json").is_err());
+        assert!(parse_http_url("example.com").is_err());
+    }
+}
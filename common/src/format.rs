@@ -0,0 +1,156 @@
+//! Output format selection.
+
+use core::{fmt, str::FromStr};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// Output formats supported by the command line tools.
+///
+/// Deliberately not `#[non_exhaustive]`: the tools match on it without a
+/// wildcard arm, so adding a format fails to compile until every tool
+/// handles it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    #[default]
+    Ical,
+    Json,
+}
+
+impl OutputFormat {
+    /// The media type of the format.
+    #[must_use]
+    pub const fn media_type(self) -> &'static str {
+        match self {
+            Self::Ical => "text/calendar",
+            Self::Json => "application/json",
+        }
+    }
+
+    /// The file name extension of the format.
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Ical => "ics",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// The `--format` argument: one format, or all of them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FormatArg {
+    #[default]
+    Ical,
+    Json,
+    All,
+}
+
+impl FormatArg {
+    /// The formats to write. `All` takes every [`OutputFormat`], so new
+    /// formats are included without changes here.
+    #[must_use]
+    pub fn formats(self) -> &'static [OutputFormat] {
+        match self {
+            Self::Ical => &[OutputFormat::Ical],
+            Self::Json => &[OutputFormat::Json],
+            Self::All => OutputFormat::value_variants(),
+        }
+    }
+}
+
+/// Writes the name used on the command line.
+impl fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.to_possible_value() {
+            Some(value) => f.write_str(value.get_name()),
+            None => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+/// Parses the name used on the command line, ignoring case.
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        <Self as ValueEnum>::from_str(value, true)
+    }
+}
+
+/// Choose an output format from an HTTP `Accept` header.
+///
+/// The media range with the highest quality wins, preferring exact types
+/// over wildcards. Falls back to [`OutputFormat::Ical`] if nothing matches.
+#[must_use]
+pub fn format_from_accept(header: &str) -> OutputFormat {
+    let mut best: Option<(OutputFormat, (f32, bool))> = None;
+    for range in header.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let media_type = params.next().unwrap_or_default().to_ascii_lowercase();
+        let quality = params
+            .find_map(|param| param.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        let (format, exact) = match media_type.as_str() {
+            "text/calendar" => (OutputFormat::Ical, true),
+            "application/json" => (OutputFormat::Json, true),
+            "text/*" | "*/*" => (OutputFormat::Ical, false),
+            "application/*" => (OutputFormat::Json, false),
+            _ => continue,
+        };
+        let rank = (quality, exact);
+        if quality > 0.0 && best.is_none_or(|(_, best)| rank > best) {
+            best = Some((format, rank));
+        }
+    }
+    best.map(|(format, _)| format).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_format_arg_all() {
+        assert_eq!(FormatArg::All.formats(), OutputFormat::value_variants());
+    }
+
+    #[test]
+    fn test_display_from_str() {
+        assert_eq!(OutputFormat::Ical.to_string(), "ical");
+        assert_eq!(OutputFormat::Json.to_string(), "json");
+        assert_eq!("json".parse(), Ok(OutputFormat::Json));
+        assert_eq!("ICAL".parse(), Ok(OutputFormat::Ical));
+        assert!("ics".parse::<OutputFormat>().is_err());
+        for format in OutputFormat::value_variants() {
+            assert_eq!(format.to_string().parse(), Ok(*format));
+        }
+    }
+
+    #[test]
+    fn test_format_from_accept() {
+        assert_eq!(format_from_accept("text/calendar"), OutputFormat::Ical);
+        assert_eq!(format_from_accept("application/json"), OutputFormat::Json);
+        assert_eq!(format_from_accept("*/*"), OutputFormat::Ical);
+        assert_eq!(format_from_accept(""), OutputFormat::Ical);
+        assert_eq!(format_from_accept("text/html"), OutputFormat::Ical);
+        assert_eq!(
+            format_from_accept("*/*, Application/JSON"),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format_from_accept("text/calendar;q=0.5, application/json;q=0.9, */*;q=0.1"),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format_from_accept("application/json; q=0, */*"),
+            OutputFormat::Ical
+        );
+        assert_eq!(
+            format_from_accept("text/html,application/xhtml+xml,application/*;q=0.9,*/*;q=0.8"),
+            OutputFormat::Json
+        );
+    }
+}
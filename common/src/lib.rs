@@ -0,0 +1,165 @@
+//! Output format and file helpers shared by the command line tools.
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use serde::de::DeserializeOwned;
+
+pub mod args;
+pub mod format;
+pub mod logging;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod output;
+
+pub use format::{FormatArg, OutputFormat, format_from_accept};
+#[doc(hidden)]
+pub use git_version;
+pub use output::{AtomicFile, Output, Writer, write_each};
+
+/// Version of the build from git, or `cargo:` and the version of the
+/// calling package if git is not available.
+#[macro_export]
+macro_rules! version {
+    () => {
+        $crate::git_version::git_version!(
+            prefix = "git:",
+            cargo_prefix = "cargo:",
+            fallback = "unknown"
+        )
+    };
+}
+
+/// Parse JSON from `reader`, ignoring a leading UTF-8 byte order mark.
+///
+/// # Errors
+///
+/// Fails if reading fails, or with an error wrapping the
+/// [`serde_json::Error`] if the JSON is invalid.
+pub fn from_json_reader<T: DeserializeOwned>(mut reader: impl Read) -> io::Result<T> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    let json = buf.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&buf);
+    Ok(serde_json::from_slice(json)?)
+}
+
+#[inline]
+#[must_use]
+pub fn io_error_to_string(err: &io::Error, path: &Path) -> String {
+    format!("{err}: {}", path.display())
+}
+
+/// `err` with `path` added to the message, keeping its kind.
+#[must_use]
+pub fn io_error_with_path(err: &io::Error, path: &Path) -> io::Error {
+    io::Error::new(err.kind(), io_error_to_string(err, path))
+}
+
+/// Whether `output` was modified after `trigger`. Missing files are never
+/// up to date.
+#[must_use]
+pub fn is_newer(output: &Path, trigger: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified()).ok();
+    match (modified(output), modified(trigger)) {
+        (Some(output), Some(trigger)) => output > trigger,
+        _ => false,
+    }
+}
+
+/// Whether data is piped to stdin.
+///
+/// A terminal or `/dev/null`, as under cron, is not a pipe.
+#[must_use]
+pub fn stdin_is_piped() -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::{fd::AsFd as _, unix::fs::FileTypeExt as _};
+        io::stdin()
+            .as_fd()
+            .try_clone_to_owned()
+            .and_then(|fd| fs::File::from(fd).metadata())
+            .is_ok_and(|meta| meta.file_type().is_fifo())
+    }
+    #[cfg(not(unix))]
+    {
+        false
+    }
+}
+
+/// A warning if data is piped to stdin while the input is read from
+/// `source`, as the piped data is then ignored.
+///
+/// Only a warning, as callers such as cron jobs may leave stdin as a pipe.
+#[must_use]
+pub fn stdin_unused_warning(source: Option<&str>, piped: bool) -> Option<String> {
+    source
+        .filter(|_| piped)
+        .map(|source| format!("Reading {source}, ignoring data piped to stdin"))
+}
+
+/// A fresh directory for a test, removed by the caller.
+#[cfg(test)]
+fn test_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("common-test-{name}-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs::File;
+
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        let dir = test_dir("newer");
+        let older = dir.join("older");
+        let newer = dir.join("newer");
+        let now = std::time::SystemTime::now();
+        File::create(&older)
+            .unwrap()
+            .set_modified(now - core::time::Duration::from_mins(1))
+            .unwrap();
+        File::create(&newer).unwrap().set_modified(now).unwrap();
+        assert!(is_newer(&newer, &older));
+        assert!(!is_newer(&older, &newer));
+        assert!(!is_newer(&dir.join("missing"), &older));
+        assert!(!is_newer(&newer, &dir.join("missing")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stdin_unused_warning() {
+        assert_eq!(stdin_unused_warning(None, true), None);
+        assert_eq!(stdin_unused_warning(Some("input.json"), false), None);
+        assert_eq!(
+            stdin_unused_warning(Some("input.json"), true).as_deref(),
+            Some("Reading input.json, ignoring data piped to stdin")
+        );
+    }
+
+    #[test]
+    fn test_json_with_bom() {
+        let json = r#"{"dates":["2024-03-01"]}"#;
+        let with_bom = format!("\u{feff}{json}");
+        let value: serde_json::Value = from_json_reader(with_bom.as_bytes()).unwrap();
+        assert_eq!(value["dates"][0], "2024-03-01");
+        let value: serde_json::Value = from_json_reader(json.as_bytes()).unwrap();
+        assert_eq!(value["dates"][0], "2024-03-01");
+        let err = from_json_reader::<serde_json::Value>(b"{".as_slice()).unwrap_err();
+        assert!(err.into_inner().unwrap().is::<serde_json::Error>());
+    }
+
+    #[test]
+    fn test_io_error_with_path() {
+        let err = io_error_with_path(
+            &io::Error::from(io::ErrorKind::NotFound),
+            Path::new("/missing/out.ics"),
+        );
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().ends_with(": /missing/out.ics"), "{err}");
+    }
+}
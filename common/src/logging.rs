@@ -0,0 +1,144 @@
+//! Logging setup shared by the command line tools.
+
+use std::{env, fs::create_dir_all};
+
+use clap::{Args, ValueEnum};
+use tracing::Subscriber;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    EnvFilter, Layer, fmt, fmt::MakeWriter, prelude::*, registry::LookupSpan,
+};
+
+/// Log output format, selected with `--log-format` or `RIZWOLD_LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Logging options for a command line.
+#[derive(Debug, Clone, Args)]
+// Without this, the doc comment above becomes the help text of commands
+// without their own
+#[command(about = None, long_about = None)]
+pub struct LogArgs {
+    /// Log output format
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        env = "RIZWOLD_LOG_FORMAT",
+        default_value = "text"
+    )]
+    pub log_format: LogFormat,
+}
+
+/// A layer writing `format` to `writer`.
+pub fn fmt_layer<S, W>(format: LogFormat, writer: W) -> Box<dyn Layer<S> + Send + Sync + 'static>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = fmt::layer().with_writer(writer).with_ansi(false);
+    match format {
+        LogFormat::Text => layer.boxed(),
+        LogFormat::Json => layer.json().boxed(),
+    }
+}
+
+/// Log in `format`, filtered by `RUST_LOG`.
+///
+/// Logs go to weekly files named `file_prefix` in `RIZWOLD_LOG_DIR` if it
+/// is set, otherwise to stderr. Keep the returned guard until exit, so the
+/// last lines are written.
+pub fn init(format: LogFormat, file_prefix: &str) -> WorkerGuard {
+    #[allow(clippy::disallowed_methods)]
+    if let Some(dir) = &env::var_os("RIZWOLD_LOG_DIR") {
+        if let Err(err) = create_dir_all(dir) {
+            eprintln!("Unable to initialize logging to file: {err}");
+        } else {
+            match RollingFileAppender::builder()
+                .rotation(Rotation::WEEKLY)
+                .max_log_files(8)
+                .filename_prefix(file_prefix)
+                .build(dir)
+            {
+                Err(err) => {
+                    eprintln!(
+                        "Unable to initialize logging in directory: {}",
+                        dir.display()
+                    );
+                    eprintln!("Cause: {err}");
+                }
+                Ok(file_appender) => {
+                    let (non_blocking, guard) = tracing_appender::non_blocking(file_appender);
+
+                    tracing_subscriber::registry()
+                        .with(EnvFilter::from_default_env())
+                        .with(fmt_layer(format, non_blocking))
+                        .init();
+                    return guard;
+                }
+            }
+        }
+    }
+    let (non_blocking, guard) = tracing_appender::non_blocking(std::io::stderr());
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(fmt_layer(format, non_blocking))
+        .init();
+
+    guard
+}
+
+#[cfg(test)]
+mod test {
+    extern crate alloc;
+    use alloc::sync::Arc;
+    use std::{io, sync::Mutex};
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_no_help_text() {
+        use clap::{CommandFactory as _, Parser};
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            log: LogArgs,
+        }
+        assert!(Cli::command().get_about().is_none());
+    }
+
+    #[test]
+    fn test_json_log_format() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber =
+            tracing_subscriber::registry().with(fmt_layer(LogFormat::Json, move || writer.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(count = 3, "Exported calendar events");
+        });
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains(r#""count":3"#), "{output}");
+    }
+}
@@ -0,0 +1,129 @@
+//! HTTP servers on localhost for testing clients, enabled by the `mock`
+//! feature.
+//!
+//! The servers panic on any failure, failing the test using them.
+#![allow(clippy::unwrap_used)]
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use std::{
+    io::{Read as _, Write as _},
+    net::{TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+};
+
+use url::Url;
+
+/// Read a request head from `stream`, or `None` if the client closed the
+/// connection or stopped sending before the end of it.
+fn read_head(stream: &mut TcpStream) -> Option<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.ends_with(b"\r\n\r\n") {
+        match stream.read(&mut buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => request.extend_from_slice(&buf[..n]),
+        }
+    }
+    Some(request)
+}
+
+/// Respond with `body` as JSON, asking the client to close the connection
+/// unless `keep_alive`.
+fn respond(stream: &mut TcpStream, body: &str, keep_alive: bool) {
+    let connection = if keep_alive {
+        ""
+    } else {
+        "Connection: close\r\n"
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n{connection}\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+}
+
+/// Bind a listener on a free port, and its base URL.
+fn listen() -> (TcpListener, Url) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    (listener, base_url)
+}
+
+/// Serve one request, responding with `body`. The thread returns the
+/// request head, or an empty string if the client sent none.
+#[must_use]
+pub fn serve_once(body: &'static str) -> (Url, JoinHandle<String>) {
+    let (listener, base_url) = listen();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let Some(request) = read_head(&mut stream) else {
+            return String::new();
+        };
+        respond(&mut stream, body, false);
+        String::from_utf8(request).unwrap()
+    });
+    (base_url, server)
+}
+
+/// Serve every request with `body`, counting the requests.
+#[must_use]
+pub fn serve_counting(body: &'static str) -> (Url, Arc<AtomicUsize>) {
+    let (listener, base_url) = listen();
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&hits);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            if read_head(&mut stream).is_some() {
+                counter.fetch_add(1, Ordering::SeqCst);
+                respond(&mut stream, body, false);
+            }
+        }
+    });
+    (base_url, hits)
+}
+
+/// Serve `requests` requests with `body`, keeping connections open. The
+/// thread returns the number of connections used.
+#[must_use]
+pub fn serve_keep_alive(body: &'static str, requests: usize) -> (Url, JoinHandle<usize>) {
+    let (listener, base_url) = listen();
+    let server = thread::spawn(move || {
+        let mut connections = 0;
+        let mut served = 0;
+        while served < requests {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .set_read_timeout(Some(core::time::Duration::from_secs(1)))
+                .unwrap();
+            connections += 1;
+            while served < requests && read_head(&mut stream).is_some() {
+                respond(&mut stream, body, true);
+                served += 1;
+            }
+        }
+        connections
+    });
+    (base_url, server)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serve_once_closed() {
+        let (base_url, server) = serve_once("{}");
+        let addr = format!(
+            "{}:{}",
+            base_url.host_str().unwrap(),
+            base_url.port().unwrap()
+        );
+        drop(TcpStream::connect(addr).unwrap());
+        assert_eq!(server.join().unwrap(), "");
+    }
+}
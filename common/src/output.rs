@@ -0,0 +1,494 @@
+//! Where the command line tools write their output.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io::{self, StdoutLock, Write, stdout},
+    path::{Path, PathBuf},
+};
+
+use crate::{OutputFormat, io_error_to_string, io_error_with_path, is_newer};
+
+/// Where to write: a file from `--output`, files in `--output-dir`, or
+/// stdout.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Output {
+    #[default]
+    Stdout,
+    File(PathBuf),
+    /// A path without extension, each format is written to it with its
+    /// own extension added.
+    Base(PathBuf),
+}
+
+impl Output {
+    /// The output for `--output`, stdout if it is omitted or `-`.
+    #[must_use]
+    pub fn new(path: Option<&Path>) -> Self {
+        match path {
+            Some(path) if path != Path::new("-") => Self::File(path.to_path_buf()),
+            _ => Self::Stdout,
+        }
+    }
+
+    /// Files named `name` plus the extension of each format in
+    /// `--output-dir`.
+    #[must_use]
+    pub fn in_dir(dir: &Path, name: &str) -> Self {
+        Self::Base(dir.join(name))
+    }
+
+    /// The path of the file, or `None` for stdout and a base path.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Stdout | Self::Base(_) => None,
+            Self::File(path) => Some(path),
+        }
+    }
+
+    /// The output of each of `formats`.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] for several formats and a
+    /// single file.
+    pub fn each(&self, formats: &[OutputFormat]) -> Result<Vec<Self>, io::Error> {
+        match self {
+            Self::Stdout => Ok(vec![Self::Stdout; formats.len()]),
+            Self::File(path) if formats.len() > 1 => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Several formats need an output directory, not a file: {}",
+                    path.display()
+                ),
+            )),
+            Self::File(_) => Ok(vec![self.clone(); formats.len()]),
+            Self::Base(base) => Ok(formats
+                .iter()
+                .map(|format| {
+                    let mut path = base.clone().into_os_string();
+                    path.push(".");
+                    path.push(format.extension());
+                    Self::File(path.into())
+                })
+                .collect()),
+        }
+    }
+
+    /// Whether every file of `formats` was modified after `trigger`.
+    /// Stdout is never up to date.
+    #[must_use]
+    pub fn is_newer(&self, formats: &[OutputFormat], trigger: &Path) -> bool {
+        self.each(formats).is_ok_and(|outputs| {
+            outputs
+                .iter()
+                .all(|output| output.path().is_some_and(|path| is_newer(path, trigger)))
+        })
+    }
+
+    /// Open the output for writing. A file is written to a temporary file
+    /// and only appears at its path after [`Writer::commit`].
+    ///
+    /// # Errors
+    ///
+    /// Fails with the path in the message if the file cannot be created,
+    /// for example when its directory does not exist. A base path cannot
+    /// be opened, see [`Output::create_each`].
+    pub fn create(&self) -> Result<Writer, io::Error> {
+        match self {
+            Self::Stdout => Ok(Writer::Stdout(stdout().lock())),
+            Self::File(path) => AtomicFile::create(path)
+                .map(Writer::File)
+                .map_err(|err| io_error_with_path(&err, path)),
+            Self::Base(path) => Err(io_error_with_path(
+                &io::Error::from(io::ErrorKind::InvalidInput),
+                path,
+            )),
+        }
+    }
+
+    /// Open a writer for each of `formats`, see [`Output::each`].
+    ///
+    /// # Errors
+    ///
+    /// Fails if any of the files cannot be created.
+    pub fn create_each(&self, formats: &[OutputFormat]) -> Result<Vec<Writer>, io::Error> {
+        self.each(formats)?.iter().map(Self::create).collect()
+    }
+}
+
+/// Write each of `outputs` to its writer from [`Output::create_each`].
+///
+/// Sets the file `mode` if given, then commits them all. Stdout marks
+/// where each format after the first starts with `--- <extension> ---`.
+///
+/// # Errors
+///
+/// Fails if writing, setting the mode or committing fails. Files are only
+/// committed once all of them are written.
+pub fn write_each(
+    mut writers: Vec<Writer>,
+    formats: &[OutputFormat],
+    outputs: Vec<String>,
+    mode: Option<u32>,
+) -> Result<(), io::Error> {
+    for (i, ((writer, format), output)) in writers.iter_mut().zip(formats).zip(outputs).enumerate()
+    {
+        if i > 0 && writer.path().is_none() {
+            writeln!(writer, "--- {} ---", format.extension())?;
+        }
+        writer
+            .write_all(output.as_bytes())
+            .map_err(|err| writer.describe(&err))?;
+        if let Some(mode) = mode {
+            writer.set_mode(mode)?;
+        }
+    }
+    writers.into_iter().try_for_each(Writer::commit)
+}
+
+/// An opened [`Output`].
+#[derive(Debug)]
+pub enum Writer {
+    Stdout(StdoutLock<'static>),
+    File(AtomicFile),
+}
+
+impl Writer {
+    /// The path of the file, or `None` for stdout.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Self::Stdout(_) => None,
+            Self::File(file) => Some(file.path()),
+        }
+    }
+
+    /// `err` with the path of the file added to the message.
+    fn describe(&self, err: &io::Error) -> io::Error {
+        self.path().map_or_else(
+            || io::Error::new(err.kind(), err.to_string()),
+            |path| io_error_with_path(err, path),
+        )
+    }
+
+    /// Set the Unix file mode of a file, see [`AtomicFile::set_mode`].
+    /// Does nothing for stdout.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the permissions cannot be changed.
+    pub fn set_mode(&self, mode: u32) -> Result<(), io::Error> {
+        match self {
+            Self::Stdout(_) => Ok(()),
+            Self::File(file) => file.set_mode(mode).map_err(|err| self.describe(&err)),
+        }
+    }
+
+    /// Rename a file into place, or flush stdout.
+    ///
+    /// # Errors
+    ///
+    /// Fails if flushing, syncing or renaming fails.
+    pub fn commit(self) -> Result<(), io::Error> {
+        match self {
+            Self::Stdout(mut out) => out.flush(),
+            Self::File(file) => {
+                let path = file.path().to_path_buf();
+                file.commit().map_err(|err| io_error_with_path(&err, &path))
+            }
+        }
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Stdout(out) => out.write(buf),
+            Self::File(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Stdout(out) => out.flush(),
+            Self::File(file) => file.flush(),
+        }
+    }
+}
+
+/// A file written under a temporary name next to `path` and renamed into
+/// place by [`AtomicFile::commit`].
+///
+/// Dropping it without committing removes the temporary file, leaving any
+/// existing file at `path` untouched.
+#[derive(Debug)]
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Start writing a file to `path`.
+    ///
+    /// The temporary file is named after `path`, the process id and a
+    /// counter, so concurrent writers do not share it. It gets the
+    /// permissions of any existing file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the temporary file cannot be created.
+    pub fn create(path: &Path) -> Result<Self, io::Error> {
+        static COUNTER: AtomicU32 = AtomicU32::new(0);
+        let (tmp, file) = loop {
+            let mut name = OsString::from(".");
+            name.push(path.file_name().unwrap_or_default());
+            name.push(format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let tmp = path.with_file_name(name);
+            match File::options().write(true).create_new(true).open(&tmp) {
+                Ok(file) => break (tmp, file),
+                // Left behind by an earlier process with the same id
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err),
+            }
+        };
+        if let Ok(meta) = fs::metadata(path) {
+            file.set_permissions(meta.permissions())?;
+        }
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp,
+            file,
+            committed: false,
+        })
+    }
+
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Set the Unix file mode, so it is in place when the file appears at
+    /// `path`. Ignored with a warning on other platforms.
+    ///
+    /// # Errors
+    ///
+    /// Fails if the permissions cannot be changed.
+    pub fn set_mode(&self, mode: u32) -> Result<(), io::Error> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt as _;
+            self.file.set_permissions(fs::Permissions::from_mode(mode))
+        }
+        #[cfg(not(unix))]
+        {
+            tracing::warn!(
+                mode = format!("{mode:o}"),
+                "File mode is only supported on Unix"
+            );
+            Ok(())
+        }
+    }
+
+    /// Flush the written data and rename the file into place.
+    ///
+    /// # Errors
+    ///
+    /// Fails if syncing or renaming the file fails.
+    pub fn commit(mut self) -> Result<(), io::Error> {
+        self.file.sync_all()?;
+        fs::rename(&self.tmp, &self.path)?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed
+            && let Err(err) = fs::remove_file(&self.tmp)
+        {
+            tracing::warn!("{}", io_error_to_string(&err, &self.tmp));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_dir;
+
+    #[test]
+    fn test_output_new() {
+        assert_eq!(Output::new(None), Output::Stdout);
+        assert_eq!(Output::new(Some(Path::new("-"))), Output::Stdout);
+        assert_eq!(
+            Output::new(Some(Path::new("out.ics"))),
+            Output::File(PathBuf::from("out.ics"))
+        );
+        assert_eq!(
+            Output::in_dir(Path::new("calendars"), "7530"),
+            Output::Base(PathBuf::from("calendars/7530"))
+        );
+    }
+
+    #[test]
+    fn test_each() {
+        let formats = [OutputFormat::Ical, OutputFormat::Json];
+        assert_eq!(
+            Output::Stdout.each(&formats).unwrap(),
+            [Output::Stdout, Output::Stdout]
+        );
+        assert_eq!(
+            Output::in_dir(Path::new("calendars"), "Svingen 2.5")
+                .each(&formats)
+                .unwrap(),
+            [
+                Output::File(PathBuf::from("calendars/Svingen 2.5.ics")),
+                Output::File(PathBuf::from("calendars/Svingen 2.5.json")),
+            ]
+        );
+        let file = Output::new(Some(Path::new("out.ics")));
+        assert_eq!(
+            file.each(&formats[1..]).unwrap(),
+            core::slice::from_ref(&file)
+        );
+        let err = file.each(&formats).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_create() {
+        assert!(matches!(Output::new(None).create(), Ok(Writer::Stdout(_))));
+        assert!(matches!(
+            Output::new(Some(Path::new("-"))).create(),
+            Ok(Writer::Stdout(_))
+        ));
+
+        let dir = test_dir("create");
+        let path = dir.join("out.ics");
+        let mut writer = Output::new(Some(&path)).create().unwrap();
+        assert_eq!(writer.path(), Some(path.as_path()));
+        writer.write_all(b"BEGIN:VCALENDAR").unwrap();
+        assert!(!path.exists());
+        writer.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "BEGIN:VCALENDAR");
+
+        let missing = dir.join("missing").join("out.ics");
+        let err = Output::new(Some(&missing)).create().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(
+            err.to_string().ends_with(&missing.display().to_string()),
+            "{err}"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_create_each() {
+        let dir = test_dir("create-each");
+        let output = Output::in_dir(&dir, "out");
+        let formats = [OutputFormat::Ical, OutputFormat::Json];
+        assert!(!output.is_newer(&formats, &dir));
+        let writers = output.create_each(&formats).unwrap();
+        write_each(
+            writers,
+            &formats,
+            vec!["ical".to_string(), "json".to_string()],
+            None,
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(dir.join("out.ics")).unwrap(), "ical");
+        assert_eq!(fs::read_to_string(dir.join("out.json")).unwrap(), "json");
+
+        let trigger = dir.join("trigger");
+        File::create(&trigger)
+            .unwrap()
+            .set_modified(std::time::SystemTime::now() - core::time::Duration::from_mins(1))
+            .unwrap();
+        assert!(output.is_newer(&formats, &trigger));
+        fs::remove_file(dir.join("out.json")).unwrap();
+        assert!(!output.is_newer(&formats, &trigger));
+        assert!(output.is_newer(&formats[..1], &trigger));
+        assert!(!Output::Stdout.is_newer(&formats[..1], &trigger));
+
+        let file = Output::new(Some(&dir.join("only.json")));
+        let writers = file.create_each(&formats[1..]).unwrap();
+        write_each(writers, &formats[1..], vec!["only".to_string()], None).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("only.json")).unwrap(), "only");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_keeps_original_file() {
+        let dir = test_dir("atomic");
+        let path = dir.join("out.ics");
+        fs::write(&path, "original").unwrap();
+
+        let write = |file: &mut AtomicFile| -> io::Result<()> {
+            file.write_all(b"partial")?;
+            Err(io::Error::other("disk full"))
+        };
+        let mut file = AtomicFile::create(&path).unwrap();
+        assert!(write(&mut file).is_err());
+        drop(file);
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let dir = test_dir("concurrent");
+        let path = dir.join("out.ics");
+        let mut first = AtomicFile::create(&path).unwrap();
+        let mut second = AtomicFile::create(&path).unwrap();
+        first.write_all(b"first").unwrap();
+        second.write_all(b"second").unwrap();
+        first.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+        second.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_keeps_mode() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = test_dir("mode");
+        let path = dir.join("out.ics");
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+        fs::write(&path, "original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        AtomicFile::create(&path).unwrap().commit().unwrap();
+        assert_eq!(mode(&path), 0o640);
+
+        let file = AtomicFile::create(&path).unwrap();
+        file.set_mode(0o604).unwrap();
+        file.commit().unwrap();
+        assert_eq!(mode(&path), 0o604);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,9 +1,12 @@
 //! iCalendar generator
 
 use core::num::NonZeroU8;
+extern crate alloc;
+use alloc::collections::BTreeMap;
 
+use ::calendar::{Alarm, AlarmAction};
 use chrono::{
-    DateTime, Datelike, NaiveDate, Utc,
+    DateTime, Datelike, Duration, NaiveDate, Utc,
     Weekday::{Fri, Mon, Sat, Sun, Thu, Tue, Wed},
 };
 use url::Url;
@@ -26,6 +29,60 @@ fn weekday(date: NaiveDate) -> &'static str {
     }
 }
 
+/// Event order in the calendar.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SortOrder {
+    /// Chronological across all fractions.
+    #[default]
+    Date,
+    /// Grouped by fraction, then chronological.
+    Fraction,
+}
+
+/// What makes event UIDs unique.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum UidScope {
+    /// Date, fractions and address, so each address has its own events.
+    #[default]
+    Address,
+    /// Date and fractions only, so merged calendars share identical events.
+    Global,
+}
+
+/// Options controlling how pickup dates become events.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// Combine fractions collected on the same date into one event.
+    pub merge_same_day: bool,
+    /// Add the pickup frequency in weeks as `X-FRACTION-FREQUENCY`.
+    pub include_frequency: bool,
+    /// Event order. Merged events are always in date order.
+    pub sort: SortOrder,
+    /// `URL` for events of a fraction, by fraction id, instead of the
+    /// calendar URL. Not used for merged events with several fractions.
+    pub fraction_urls: BTreeMap<String, Url>,
+    /// What goes into event UIDs.
+    pub uid_scope: UidScope,
+    /// Remind this long before every event.
+    pub reminder: Option<Duration>,
+    /// Reminder for events of a fraction, by fraction id, instead of
+    /// `reminder`. Merged events use the longest of their fractions.
+    pub fraction_reminders: BTreeMap<String, Duration>,
+    /// What reminders do.
+    pub reminder_action: AlarmAction,
+}
+
+/// A single pickup of one waste fraction.
+#[derive(Debug, Clone)]
+pub struct DisposalDateEntry {
+    pub date: NaiveDate,
+    pub fraction: WasteFraction,
+    /// Weeks between pickups.
+    pub frequency: u8,
+    /// How long before the pickup to remind, if at all.
+    pub reminder: Option<Duration>,
+}
+
 #[derive(Debug, Clone)]
 pub struct Calendar {
     namespace: Uuid,
@@ -33,62 +90,80 @@ pub struct Calendar {
     created: DateTime<Utc>,
     address: DisposalAddress,
     url: url::Url,
+    options: Options,
 }
 
 impl From<Calendar> for ::calendar::Calendar {
     fn from(calendar: Calendar) -> Self {
+        let entries = calendar.entries();
+        let events = if calendar.options.merge_same_day {
+            let mut by_date: BTreeMap<NaiveDate, Vec<DisposalDateEntry>> = BTreeMap::new();
+            for entry in entries {
+                by_date.entry(entry.date).or_default().push(entry);
+            }
+            by_date
+                .into_values()
+                .map(|mut entries| {
+                    entries.sort_by(|a, b| a.fraction.cmp(&b.fraction));
+                    entries.dedup_by(|a, b| a.fraction == b.fraction);
+                    calendar.event(&entries)
+                })
+                .collect()
+        } else {
+            entries
+                .into_iter()
+                .map(|entry| calendar.event(&[entry]))
+                .collect()
+        };
         Self {
             name: Some("Søppeltømming Innherred Renovasjon".to_string()),
             description: None,
             prodid: "-//Aasan//Aasan Innherred Renovasjon//EN".to_string(),
-            events: calendar
-                .fractions
-                .iter()
-                .flat_map(move |fraction| {
-                    let address = calendar.address.clone();
-                    let url = calendar.url.clone();
-                    let waste_fraction: WasteFraction = fraction.into();
-                    let icon = waste_fraction.icon();
-                    let name = waste_fraction.name();
-                    fraction.dates.iter().map(move |dt| {
-                        let date = dt.date();
-                        let weekday = weekday(date);
-                        let day = date.day();
-                        let summary = format!("{icon} {name} {weekday} {day}.");
-
-                        ::calendar::Event {
-                            uid: generate_stable_uid(
-                                calendar.namespace,
-                                &address,
-                                date,
-                                &waste_fraction,
-                            ),
-                            dtstamp: calendar.created,
-                            sequence: calendar.created.timestamp(),
-                            date: dt.date(),
-                            summary,
-                            url: Some(url.clone()),
-                            duration: NonZeroU8::MIN,
-                            rrule: None,
-                            rdates: Vec::new(),
-                            exdates: Vec::new(),
-                            description: None,
-                            recurrence_id: None,
-                        }
-                    })
-                })
-                .collect(),
+            events,
+            options: ::calendar::RenderOptions::default(),
+            attendees: Vec::new(),
+            organizer: None,
+            color: None,
+            refresh_interval: None,
+            calscale: Some(::calendar::DEFAULT_CALSCALE.to_string()),
         }
     }
 }
 
+/// The number of weeks between `dates` if they are evenly spaced by whole
+/// weeks, to decide whether they can be written as a weekly `RRULE`.
+///
+/// The order of `dates` does not matter and repeated dates are ignored.
+/// Fewer than two distinct dates have no interval, so give `None`.
+#[must_use]
+pub fn infer_weekly_interval(dates: &[NaiveDate]) -> Option<u32> {
+    let mut dates = dates.to_vec();
+    dates.sort_unstable();
+    dates.dedup();
+    let mut gaps = dates.windows(2).map(|pair| (pair[1] - pair[0]).num_days());
+    let gap = gaps.next()?;
+    if gap % 7 != 0 || gaps.any(|other| other != gap) {
+        return None;
+    }
+    u32::try_from(gap / 7).ok()
+}
+
+/// A UID from `date` and `fractions`, and `address` unless it is `None`.
 fn generate_stable_uid(
     namespace: Uuid,
-    address: &DisposalAddress,
+    address: Option<&DisposalAddress>,
     date: NaiveDate,
-    fraction: &WasteFraction,
+    fractions: &[WasteFraction],
 ) -> Uuid {
-    let input_data = format!("{}-{}-{}", address, date, fraction.get_id());
+    let ids = fractions
+        .iter()
+        .map(WasteFraction::get_id)
+        .collect::<Vec<_>>()
+        .join("+");
+    let input_data = address.map_or_else(
+        || format!("{date}-{ids}"),
+        |address| format!("{address}-{date}-{ids}"),
+    );
     Uuid::new_v5(&namespace, input_data.as_bytes())
 }
 
@@ -100,6 +175,7 @@ impl Calendar {
         address: DisposalAddress,
         created: DateTime<Utc>,
         url: Url,
+        options: Options,
     ) -> Self {
         Self {
             namespace,
@@ -107,6 +183,333 @@ impl Calendar {
             created,
             address,
             url,
+            options,
+        }
+    }
+
+    /// One entry per pickup date of each fraction, in the configured order.
+    #[must_use]
+    pub fn entries(&self) -> Vec<DisposalDateEntry> {
+        let mut entries: Vec<DisposalDateEntry> = self
+            .fractions
+            .iter()
+            .flat_map(|fraction| {
+                let waste_fraction: WasteFraction = fraction.into();
+                let reminder = self
+                    .options
+                    .fraction_reminders
+                    .get(&waste_fraction.get_id())
+                    .copied()
+                    .or(self.options.reminder);
+                fraction.dates.iter().map(move |dt| DisposalDateEntry {
+                    date: dt.date(),
+                    fraction: waste_fraction.clone(),
+                    frequency: fraction.frequency,
+                    reminder,
+                })
+            })
+            .collect();
+        match self.options.sort {
+            SortOrder::Date => {
+                entries.sort_by(|a, b| (a.date, &a.fraction).cmp(&(b.date, &b.fraction)));
+            }
+            SortOrder::Fraction => {
+                entries.sort_by(|a, b| (&a.fraction, a.date).cmp(&(&b.fraction, b.date)));
+            }
+        }
+        entries
+    }
+
+    /// Build one event for entries sharing the same date.
+    fn event(&self, entries: &[DisposalDateEntry]) -> ::calendar::Event {
+        let date = entries[0].date;
+        let fractions: Vec<WasteFraction> = entries.iter().map(|e| e.fraction.clone()).collect();
+        let icons: String = fractions.iter().map(WasteFraction::icon).collect();
+        let names = fractions
+            .iter()
+            .map(WasteFraction::name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let weekday = weekday(date);
+        let day = date.day();
+        let summary = format!("{icons} {names} {weekday} {day}.");
+        let mut extensions = Vec::new();
+        if self.options.include_frequency {
+            let frequency = entries
+                .iter()
+                .map(|e| e.frequency.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            extensions.push(("X-FRACTION-FREQUENCY".to_string(), frequency));
+        }
+
+        let url = match fractions.as_slice() {
+            [fraction] => self.options.fraction_urls.get(&fraction.get_id()),
+            _ => None,
+        };
+        let alarm = entries
+            .iter()
+            .filter_map(|entry| entry.reminder)
+            .max()
+            .map(|before| Alarm::new(before, self.options.reminder_action));
+
+        ::calendar::Event {
+            uid: generate_stable_uid(
+                self.namespace,
+                (self.options.uid_scope == UidScope::Address).then_some(&self.address),
+                date,
+                &fractions,
+            ),
+            dtstamp: self.created,
+            sequence: self.created.timestamp(),
+            date,
+            summary,
+            url: Some(url.unwrap_or(&self.url).clone()),
+            duration: NonZeroU8::MIN,
+            rrule: None,
+            rdates: Vec::new(),
+            exdates: Vec::new(),
+            description: None,
+            recurrence_id: None,
+            this_and_future: false,
+            geo: None,
+            alarm,
+            extensions,
+        }
+    }
+
+    /// Number of pickup dates for each waste fraction.
+    #[must_use]
+    pub fn count_by_fraction(&self) -> BTreeMap<WasteFraction, usize> {
+        let mut counts = BTreeMap::new();
+        for fraction in &self.fractions {
+            *counts.entry(fraction.into()).or_default() += fraction.dates.len();
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+
+    use super::*;
+
+    fn fraction(id: &str, name: &str, dates: &[&str]) -> GarbageFraction {
+        GarbageFraction {
+            fraction_id: id.to_string(),
+            fraction_name: name.to_string(),
+            frequency: 2,
+            dates: dates
+                .iter()
+                .map(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").unwrap())
+                .collect(),
         }
     }
+
+    fn calendar(fractions: Vec<GarbageFraction>, options: Options) -> Calendar {
+        Calendar::new(
+            Uuid::nil(),
+            fractions,
+            "Svingen 2".into(),
+            DateTime::from_timestamp(0, 0).unwrap(),
+            Url::parse("https://example.com/").unwrap(),
+            options,
+        )
+    }
+
+    #[test]
+    fn test_count_by_fraction() {
+        let cal = calendar(
+            vec![
+                fraction(
+                    "1111",
+                    "Matavfall",
+                    &["2026-02-10T00:00:00", "2026-02-24T00:00:00"],
+                ),
+                fraction("9992", "Restavfall", &["2026-03-10T00:00:00"]),
+            ],
+            Options::default(),
+        );
+        let counts = cal.count_by_fraction();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&WasteFraction::FoodWaste], 2);
+        assert_eq!(counts[&WasteFraction::ResidualWaste], 1);
+    }
+
+    #[test]
+    fn test_merge_same_day() {
+        let fractions = vec![
+            fraction("1111", "Matavfall", &["2026-02-12T00:00:00"]),
+            fraction("5", "Glass- og metallemballasje", &["2026-02-12T00:00:00"]),
+        ];
+        let separate: ::calendar::Calendar = calendar(fractions.clone(), Options::default()).into();
+        assert_eq!(separate.events.len(), 2);
+
+        let merged: ::calendar::Calendar = calendar(
+            fractions,
+            Options {
+                merge_same_day: true,
+                ..Options::default()
+            },
+        )
+        .into();
+        assert_eq!(merged.events.len(), 1);
+        assert_eq!(
+            merged.events[0].summary,
+            "🍌🥫 Matavfall, Glass- og metallemballasje torsdag 12."
+        );
+        assert!(
+            separate
+                .events
+                .iter()
+                .all(|e| e.uid != merged.events[0].uid)
+        );
+    }
+
+    #[test]
+    fn test_include_frequency() {
+        let cal: ::calendar::Calendar = calendar(
+            vec![fraction("1111", "Matavfall", &["2026-02-10T00:00:00"])],
+            Options {
+                include_frequency: true,
+                ..Options::default()
+            },
+        )
+        .into();
+        assert!(cal.to_string().contains("\r\nX-FRACTION-FREQUENCY:2\r\n"));
+    }
+
+    #[test]
+    fn test_fraction_urls() {
+        let food_url = Url::parse("https://example.com/matavfall").unwrap();
+        let cal: ::calendar::Calendar = calendar(
+            vec![
+                fraction("1111", "Matavfall", &["2026-02-10T00:00:00"]),
+                fraction("9992", "Restavfall", &["2026-02-17T00:00:00"]),
+            ],
+            Options {
+                fraction_urls: BTreeMap::from([("1111".to_string(), food_url.clone())]),
+                ..Options::default()
+            },
+        )
+        .into();
+        assert_eq!(cal.events[0].url, Some(food_url));
+        assert_eq!(
+            cal.events[1].url,
+            Some(Url::parse("https://example.com/").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_infer_weekly_interval() {
+        let dates = |dates: &[&str]| {
+            dates
+                .iter()
+                .map(|date| date.parse::<NaiveDate>().unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            infer_weekly_interval(&dates(&["2026-02-10", "2026-02-24", "2026-03-10"])),
+            Some(2)
+        );
+        assert_eq!(
+            infer_weekly_interval(&dates(&[
+                "2026-03-10",
+                "2026-02-10",
+                "2026-02-17",
+                "2026-03-03",
+                "2026-02-24",
+                "2026-02-17"
+            ])),
+            Some(1)
+        );
+        // Irregular, or even but not whole weeks
+        assert_eq!(
+            infer_weekly_interval(&dates(&["2026-02-10", "2026-02-24", "2026-03-03"])),
+            None
+        );
+        assert_eq!(
+            infer_weekly_interval(&dates(&["2026-02-10", "2026-02-20", "2026-03-02"])),
+            None
+        );
+        assert_eq!(infer_weekly_interval(&dates(&["2026-02-10"])), None);
+        assert_eq!(
+            infer_weekly_interval(&dates(&["2026-02-10", "2026-02-10"])),
+            None
+        );
+        assert_eq!(infer_weekly_interval(&[]), None);
+    }
+
+    #[test]
+    fn test_uid_scope() {
+        let uids = |address: &str, uid_scope| {
+            let cal: ::calendar::Calendar = Calendar::new(
+                Uuid::nil(),
+                vec![fraction("1111", "Matavfall", &["2026-02-10T00:00:00"])],
+                address.into(),
+                DateTime::from_timestamp(0, 0).unwrap(),
+                Url::parse("https://example.com/").unwrap(),
+                Options {
+                    uid_scope,
+                    ..Options::default()
+                },
+            )
+            .into();
+            cal.events.iter().map(|e| e.uid).collect::<Vec<_>>()
+        };
+        assert_ne!(
+            uids("Svingen 2", UidScope::Address),
+            uids("Svingen 4", UidScope::Address)
+        );
+        assert_eq!(
+            uids("Svingen 2", UidScope::Global),
+            uids("Svingen 4", UidScope::Global)
+        );
+        assert_ne!(
+            uids("Svingen 2", UidScope::Address),
+            uids("Svingen 2", UidScope::Global)
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let fractions = vec![
+            fraction(
+                "1111",
+                "Matavfall",
+                &["2026-02-10T00:00:00", "2026-02-24T00:00:00"],
+            ),
+            fraction("9992", "Restavfall", &["2026-02-17T00:00:00"]),
+        ];
+        let order = |sort| {
+            calendar(
+                fractions.clone(),
+                Options {
+                    sort,
+                    ..Options::default()
+                },
+            )
+            .entries()
+            .iter()
+            .map(|e| (e.date.to_string(), e.fraction.clone()))
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            order(SortOrder::Date),
+            [
+                ("2026-02-10".to_string(), WasteFraction::FoodWaste),
+                ("2026-02-17".to_string(), WasteFraction::ResidualWaste),
+                ("2026-02-24".to_string(), WasteFraction::FoodWaste),
+            ]
+        );
+        assert_eq!(
+            order(SortOrder::Fraction),
+            [
+                ("2026-02-10".to_string(), WasteFraction::FoodWaste),
+                ("2026-02-24".to_string(), WasteFraction::FoodWaste),
+                ("2026-02-17".to_string(), WasteFraction::ResidualWaste),
+            ]
+        );
+    }
 }
@@ -0,0 +1,235 @@
+//! Look up coordinates for a disposal address, to add `GEO` to events.
+use core::fmt::{self, Display};
+
+#[cfg(feature = "geocode")]
+use serde::Deserialize;
+#[cfg(feature = "geocode")]
+use ureq::{Agent, Proxy};
+#[cfg(feature = "geocode")]
+use url::Url;
+
+use crate::ir_client::DisposalAddress;
+
+/// Failure looking up coordinates.
+#[derive(Debug)]
+pub enum GeocodeError {
+    /// The request could not be sent or the response not read.
+    Request(ureq::Error),
+    /// The response has coordinates that are not numbers.
+    InvalidCoordinates(String),
+}
+
+impl Display for GeocodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(err) => write!(f, "Request failed: {err}"),
+            Self::InvalidCoordinates(value) => write!(f, "Invalid coordinates: {value}"),
+        }
+    }
+}
+
+impl core::error::Error for GeocodeError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Request(err) => Some(err),
+            Self::InvalidCoordinates(_) => None,
+        }
+    }
+}
+
+/// Resolves an address to latitude and longitude.
+pub trait Geocoder {
+    /// The coordinates of `address`, or `None` if it is not found.
+    ///
+    /// # Errors
+    ///
+    /// See [`GeocodeError`].
+    fn resolve(&self, address: &DisposalAddress) -> Result<Option<(f64, f64)>, GeocodeError>;
+}
+
+/// Finds no address, so events get no `GEO`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoGeocoder;
+
+impl Geocoder for NoGeocoder {
+    fn resolve(&self, _address: &DisposalAddress) -> Result<Option<(f64, f64)>, GeocodeError> {
+        Ok(None)
+    }
+}
+
+/// Looks up addresses in Norway with the
+/// [Nominatim](https://nominatim.org/release-docs/latest/api/Search/) search
+/// API.
+#[cfg(feature = "geocode")]
+#[derive(Debug)]
+pub struct Nominatim {
+    agent: Agent,
+    base_url: Url,
+}
+
+#[cfg(feature = "geocode")]
+impl Nominatim {
+    /// The public OpenStreetMap instance.
+    pub const DEFAULT_BASE_URL: &str = "https://nominatim.openstreetmap.org/";
+
+    /// Area searched, as `left,top,right,bottom` in degrees: the
+    /// municipalities served by Innherred Renovasjon, Frosta, Levanger,
+    /// Verdal and Inderøy. Street addresses repeat across Norway, so
+    /// searching the whole country can find the wrong town.
+    pub const VIEWBOX: &str = "10.5,64.1,13.0,63.4";
+
+    /// Search at `base_url`, see [`Self::DEFAULT_BASE_URL`].
+    ///
+    /// Without an explicit `proxy`, the proxy is read from the environment.
+    #[must_use]
+    pub fn new(proxy: Option<Proxy>, base_url: Url) -> Self {
+        let mut config = Agent::config_builder()
+            .https_only(base_url.scheme() == "https")
+            .user_agent(concat!("garbage/", env!("CARGO_PKG_VERSION")))
+            .accept("application/json");
+        if proxy.is_some() {
+            config = config.proxy(proxy);
+        }
+        Self {
+            agent: config.build().into(),
+            base_url,
+        }
+    }
+
+    /// The search URL for `address`.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn url(&self, address: &DisposalAddress) -> Url {
+        let mut url = self.base_url.join("search").expect("Should never happen");
+        url.query_pairs_mut()
+            .append_pair("q", &address.to_string())
+            .append_pair("countrycodes", "no")
+            .append_pair("viewbox", Self::VIEWBOX)
+            .append_pair("bounded", "1")
+            .append_pair("format", "jsonv2")
+            .append_pair("limit", "1");
+        url
+    }
+}
+
+/// A search result, with coordinates as strings.
+#[cfg(feature = "geocode")]
+#[derive(Debug, Deserialize)]
+struct Place {
+    lat: String,
+    lon: String,
+}
+
+#[cfg(feature = "geocode")]
+impl Place {
+    fn coordinates(&self) -> Result<(f64, f64), GeocodeError> {
+        let parse = |value: &str| {
+            value
+                .parse()
+                .map_err(|_| GeocodeError::InvalidCoordinates(value.to_string()))
+        };
+        Ok((parse(&self.lat)?, parse(&self.lon)?))
+    }
+}
+
+#[cfg(feature = "geocode")]
+impl Geocoder for Nominatim {
+    fn resolve(&self, address: &DisposalAddress) -> Result<Option<(f64, f64)>, GeocodeError> {
+        let url = self.url(address);
+        tracing::debug!(%url, %address, "Looking up coordinates");
+        let places: Vec<Place> = self
+            .agent
+            .get(url.as_str())
+            .call()
+            .and_then(|mut response| response.body_mut().read_json())
+            .map_err(GeocodeError::Request)?;
+        places.first().map(Place::coordinates).transpose()
+    }
+}
+
+/// The coordinates of `address`, if found.
+///
+/// Lookup failures are logged and give `None`.
+pub fn lookup_geo(geocoder: &dyn Geocoder, address: &DisposalAddress) -> Option<(f64, f64)> {
+    match geocoder.resolve(address) {
+        Ok(Some(geo)) => Some(geo),
+        Ok(None) => {
+            tracing::debug!(%address, "No coordinates for address");
+            None
+        }
+        Err(err) => {
+            tracing::warn!(%address, error = %err, "Geocoding failed, leaving out GEO");
+            None
+        }
+    }
+}
+
+/// Set `GEO` on every event in `cal` to the coordinates of `address`.
+///
+/// Lookup failures are logged, leaving the events without `GEO`.
+pub fn attach_geo(
+    cal: &mut ::calendar::Calendar,
+    geocoder: &dyn Geocoder,
+    address: &DisposalAddress,
+) {
+    if let Some(geo) = lookup_geo(geocoder, address) {
+        for event in &mut cal.events {
+            event.geo = Some(geo);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{calendar::Options, ir_client::schedule::DisposalDaysApi};
+
+    struct Stub(Option<(f64, f64)>);
+
+    impl Geocoder for Stub {
+        fn resolve(&self, _: &DisposalAddress) -> Result<Option<(f64, f64)>, GeocodeError> {
+            self.0
+                .ok_or_else(|| GeocodeError::InvalidCoordinates("stub".to_string()))
+                .map(Some)
+        }
+    }
+
+    fn calendar(address: &DisposalAddress) -> ::calendar::Calendar {
+        let response = crate::ir_client::schedule::from_json_reader(
+            r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00","2026-02-24T00:00:00"]}}"#.as_bytes(),
+        )
+        .unwrap();
+        DisposalDaysApi::calendar(address.clone(), response, Options::default())
+    }
+
+    #[test]
+    fn test_attach_geo() {
+        let address: DisposalAddress = "Svingen 2".parse().unwrap();
+        let mut cal = calendar(&address);
+        attach_geo(&mut cal, &Stub(Some((63.7464, 11.2996))), &address);
+        assert!(cal.events.iter().all(|e| e.geo == Some((63.7464, 11.2996))));
+        let ics = cal.to_string();
+        assert_eq!(ics.matches("\r\nGEO:63.7464;11.2996\r\n").count(), 2);
+
+        let mut cal = calendar(&address);
+        attach_geo(&mut cal, &Stub(None), &address);
+        attach_geo(&mut cal, &NoGeocoder, &address);
+        assert!(cal.events.iter().all(|e| e.geo.is_none()));
+        assert!(!cal.to_string().contains("GEO"));
+    }
+
+    #[cfg(feature = "geocode")]
+    #[test]
+    fn test_nominatim_url() {
+        let nominatim = Nominatim::new(None, Url::parse(Nominatim::DEFAULT_BASE_URL).unwrap());
+        assert_eq!(
+            nominatim.url(&"Svingen 2".into()).as_str(),
+            "https://nominatim.openstreetmap.org/search?q=Svingen+2&countrycodes=no&viewbox=10.5%2C64.1%2C13.0%2C63.4&bounded=1&format=jsonv2&limit=1"
+        );
+        let place = Place {
+            lat: "63.7464".to_string(),
+            lon: "x".to_string(),
+        };
+        assert!(place.coordinates().is_err());
+    }
+}
@@ -1,18 +1,107 @@
 //! Client for the Innherred Renovasjon WP API.
-use core::fmt::{self, Debug, Display};
+use core::{
+    fmt::{self, Debug, Display},
+    str::FromStr,
+};
+use std::io;
 
 use serde::Serialize;
 
+/// Failure getting disposal dates.
+#[derive(Debug)]
+pub enum GarbageError {
+    /// The API responded with a non-success status.
+    Http {
+        status: u16,
+        /// The start of the response body.
+        body: String,
+    },
+    /// The request could not be sent or the response not received.
+    Request(ureq::Error),
+    /// The response is not the expected JSON.
+    Decode(serde_json::Error),
+    /// Reading the input failed.
+    Io(io::Error),
+    /// The response has no fractions, usually because the address is
+    /// misspelled.
+    NoFractions(DisposalAddress),
+}
+
+impl Display for GarbageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Http { status, body } => write!(f, "HTTP status {status}: {body}"),
+            Self::Request(err) => write!(f, "Request failed: {err}"),
+            Self::Decode(err) => write!(f, "Invalid JSON: {err}"),
+            Self::Io(err) => Display::fmt(err, f),
+            Self::NoFractions(address) => {
+                write!(
+                    f,
+                    "No fractions found for address {address}, check spelling"
+                )
+            }
+        }
+    }
+}
+
+impl core::error::Error for GarbageError {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        match self {
+            Self::Http { .. } | Self::NoFractions(_) => None,
+            Self::Request(err) => Some(err),
+            Self::Decode(err) => Some(err),
+            Self::Io(err) => Some(err),
+        }
+    }
+}
+
 /// Represents an address.
+///
+/// The address is part of the generated event UIDs, so the normalization in
+/// [`FromStr`] must stay stable.
+///
+/// ```
+/// use garbage::DisposalAddress;
+/// let address: DisposalAddress = "  Svingen   2 ".parse().unwrap();
+/// assert_eq!(address.to_string(), "Svingen 2");
+/// assert!(" ".parse::<DisposalAddress>().is_err());
+/// ```
 #[derive(Debug, Clone, Serialize)]
 pub struct DisposalAddress(String);
 
+/// Kept for compatibility, uses the address verbatim.
 impl<'a> From<&'a str> for DisposalAddress {
     fn from(value: &'a str) -> Self {
         Self(value.to_string())
     }
 }
 
+#[derive(Debug)]
+/// A possible error when parsing a [`DisposalAddress`].
+pub struct InvalidAddress;
+
+impl Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Address must not be empty")
+    }
+}
+
+impl core::error::Error for InvalidAddress {}
+
+/// Trims the address and collapses internal whitespace.
+impl FromStr for DisposalAddress {
+    type Err = InvalidAddress;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let address = value.split_whitespace().collect::<Vec<_>>().join(" ");
+        if address.is_empty() {
+            Err(InvalidAddress)
+        } else {
+            Ok(Self(address))
+        }
+    }
+}
+
 impl Display for DisposalAddress {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.write_str(&self.0)
@@ -20,3 +109,19 @@ impl Display for DisposalAddress {
 }
 
 pub mod schedule;
+
+#[cfg(test)]
+mod test {
+    use super::DisposalAddress;
+
+    #[test]
+    fn test_parse_address() {
+        let address: DisposalAddress = "  Foo  12 ".parse().unwrap();
+        assert_eq!(address.0, "Foo 12");
+        let address: DisposalAddress = "Foo\t12".parse().unwrap();
+        assert_eq!(address.0, "Foo 12");
+        assert!("".parse::<DisposalAddress>().is_err());
+        assert!(" \t ".parse::<DisposalAddress>().is_err());
+        assert_eq!(DisposalAddress::from(" Foo 12").0, " Foo 12");
+    }
+}
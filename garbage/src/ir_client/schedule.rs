@@ -1,22 +1,30 @@
 //! Garbage disposal dates API.
 
 use core::fmt::Debug;
-use std::path::PathBuf;
+use std::{
+    io::{self, Read},
+    path::PathBuf,
+};
 extern crate alloc;
 use alloc::collections::BTreeMap;
 
 use chrono::{NaiveDateTime, Utc};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use ureq::Agent;
+use ureq::{Agent, Body, Proxy, http::Response};
 use url::Url;
 use uuid::Uuid;
 
-use crate::calendar::Calendar;
-use crate::{io_error_to_string, ir_client::DisposalAddress};
+use common::io_error_with_path;
+
+use crate::calendar::{Calendar, Options};
+use crate::ir_client::{DisposalAddress, GarbageError};
 
 pub type ApiResponse = BTreeMap<String, GarbageFraction>;
 
+/// Where the IR WP API is hosted.
+pub const DEFAULT_BASE_URL: &str = "https://innherredrenovasjon.no/";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GarbageFraction {
     pub fraction_id: String,
@@ -27,6 +35,10 @@ pub struct GarbageFraction {
     pub dates: Vec<NaiveDateTime>,
 }
 
+/// A waste fraction.
+///
+/// Ordered as food waste, paper and cardboard, plastic packaging, glass and
+/// metal, residual waste, then unknown fractions by id.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WasteFraction {
     FoodWaste,               // 1111
@@ -37,6 +49,23 @@ pub enum WasteFraction {
     Unknown(String, String), // (ID, Navn)
 }
 
+impl Ord for WasteFraction {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        match (self, other) {
+            (Self::Unknown(id, name), Self::Unknown(other_id, other_name)) => {
+                (id, name).cmp(&(other_id, other_name))
+            }
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+impl PartialOrd for WasteFraction {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
 impl From<GarbageFraction> for WasteFraction {
     fn from(value: GarbageFraction) -> Self {
         Self::from_api(&value.fraction_id, &value.fraction_name)
@@ -50,6 +79,27 @@ impl From<&GarbageFraction> for WasteFraction {
 }
 
 impl WasteFraction {
+    /// The fractions with built-in names and icons.
+    pub const KNOWN: [Self; 5] = [
+        Self::FoodWaste,
+        Self::PaperCardboard,
+        Self::PlasticPackaging,
+        Self::GlassMetal,
+        Self::ResidualWaste,
+    ];
+
+    /// Position in the sort order.
+    const fn rank(&self) -> u8 {
+        match self {
+            Self::FoodWaste => 0,
+            Self::PaperCardboard => 1,
+            Self::PlasticPackaging => 2,
+            Self::GlassMetal => 3,
+            Self::ResidualWaste => 4,
+            Self::Unknown(_, _) => 5,
+        }
+    }
+
     #[must_use]
     pub fn from_api(id: &str, name: &str) -> Self {
         match id {
@@ -101,77 +151,438 @@ impl WasteFraction {
 /// Disposal day provider.
 pub enum DisposalDaysApi {
     /// Fetches JSON from IR WP API.
-    Api(Agent),
+    ///
+    /// The agent is built once and reused for every request made through
+    /// this provider, so its connection pool is shared between calls.
+    Api { agent: Agent, base_url: Url },
+
+    /// Fetches JSON from any URL, such as a cache of API responses.
+    Url { agent: Agent, url: Url },
 
     /// Reads JSON from a file.
     File(Option<PathBuf>),
+
+    /// Serves a response held in memory, for tests and embedders with
+    /// their own data.
+    Memory(ApiResponse),
 }
 
 impl DisposalDaysApi {
-    /// Read dates from REST API.
-    #[allow(clippy::missing_panics_doc)]
+    /// Read dates from REST API at `base_url`, see [`DEFAULT_BASE_URL`].
+    ///
+    /// Without an explicit `proxy`, the proxy is read from the environment.
+    /// Plain HTTP is only allowed if `base_url` uses it.
     #[must_use]
-    pub fn api() -> Self {
-        let config = Agent::config_builder()
-            .https_only(true)
-            .accept("application/json")
-            .build();
-        tracing::debug!("Constructing HTTP agent with config: {config:?}");
-        Self::Api(config.into())
+    pub fn api(proxy: Option<Proxy>, base_url: Url) -> Self {
+        Self::Api {
+            agent: agent(proxy, &base_url),
+            base_url,
+        }
     }
 
+    /// Read dates from JSON at `url`, as it would be read from a file.
+    ///
+    /// Without an explicit `proxy`, the proxy is read from the environment.
+    /// Plain HTTP is only allowed if `url` uses it.
     #[must_use]
-    /// Read dates from file.
-    pub const fn file(path: Option<PathBuf>) -> Self {
-        Self::File(path)
+    pub fn from_url(proxy: Option<Proxy>, url: Url) -> Self {
+        Self::Url {
+            agent: agent(proxy, &url),
+            url,
+        }
     }
 
-    pub fn get_calendar(
-        &self,
+    #[must_use]
+    /// Read dates from file, or from stdin if `path` is omitted or `-`.
+    pub fn file(path: Option<PathBuf>) -> Self {
+        Self::File(path.filter(|path| path.as_os_str() != "-"))
+    }
+
+    #[must_use]
+    /// Serve `response` without reading a file or the network.
+    pub const fn from_response(response: ApiResponse) -> Self {
+        Self::Memory(response)
+    }
+
+    /// Build a calendar from an API response.
+    pub fn calendar(
         address: DisposalAddress,
-    ) -> Result<::calendar::Calendar, Box<dyn core::error::Error>> {
+        response: ApiResponse,
+        options: Options,
+    ) -> ::calendar::Calendar {
         const NAMESPACE: Uuid = uuid::uuid!("769d988a-38ee-48b1-908c-5d58c0982349");
-        let response: ApiResponse = self.get(&address)?;
-        tracing::debug!("Got: {response:?}");
         let created = Utc::now();
         let fractions = response.into_values().collect();
         let url =
             Url::parse("https://innherredrenovasjon.no/tommeplan/").expect("Should never happen");
-        let cal: ::calendar::Calendar =
-            Calendar::new(NAMESPACE, fractions, address, created, url).into();
-        tracing::info!("Exported {} calendar events", cal.events.len());
+        let cal = Calendar::new(NAMESPACE, fractions, address, created, url, options);
+        let summary = cal
+            .count_by_fraction()
+            .iter()
+            .map(|(fraction, count)| format!("{}: {count}", fraction.name()))
+            .collect::<Vec<_>>()
+            .join(", ");
+        tracing::info!(%summary, "Events per fraction");
+        let cal: ::calendar::Calendar = cal.into();
+        tracing::info!(count = cal.events.len(), "Exported calendar events");
 
-        Ok(cal)
+        cal
     }
 
-    /// Get a list of delivery dates.
-    #[allow(clippy::missing_errors_doc)]
-    pub fn get<T: DeserializeOwned>(
+    /// Get the disposal dates for `address` as a calendar.
+    ///
+    /// # Errors
+    ///
+    /// See [`GarbageError`].
+    pub fn get_calendar(
         &self,
-        address: &DisposalAddress,
-    ) -> Result<T, Box<dyn core::error::Error>> {
-        let response: T = match self {
-            Self::Api(client) => {
-                let url = "https://innherredrenovasjon.no/wp-json/ir/v1/garbage-disposal-dates-by-address";
-                tracing::debug!("Reading from url: {url}");
-                client
-                    .get(url)
-                    .query("address", &address.0)
-                    .call()?
-                    .body_mut()
-                    .read_json()?
+        address: DisposalAddress,
+        options: Options,
+    ) -> Result<::calendar::Calendar, GarbageError> {
+        let response = from_json_reader(self.get_raw(&address)?.as_slice())?;
+        Ok(Self::calendar(address, response, options))
+    }
+
+    /// The API URL at `base_url` with disposal dates for `address`.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn url(base_url: &Url, address: &DisposalAddress) -> Url {
+        let mut url = base_url
+            .join("wp-json/ir/v1/garbage-disposal-dates-by-address")
+            .expect("Should never happen");
+        url.query_pairs_mut().append_pair("address", &address.0);
+        url
+    }
+
+    /// Get a list of delivery dates.
+    ///
+    /// # Errors
+    ///
+    /// See [`GarbageError`].
+    #[deprecated(note = "use `get_raw` and `from_json_reader`")]
+    pub fn get<T: DeserializeOwned>(&self, address: &DisposalAddress) -> Result<T, GarbageError> {
+        from_json_reader(self.get_raw(address)?.as_slice())
+    }
+
+    /// Get the disposal dates response body as received, without parsing
+    /// it, see [`from_json_reader`].
+    ///
+    /// # Errors
+    ///
+    /// See [`GarbageError`].
+    pub fn get_raw(&self, address: &DisposalAddress) -> Result<Vec<u8>, GarbageError> {
+        match self {
+            Self::Api { agent, base_url } => {
+                let url = Self::url(base_url, address);
+                tracing::debug!(%url, %address, "Fetching disposal dates");
+                let response = agent
+                    .get(url.as_str())
+                    .call()
+                    .map_err(GarbageError::Request)?;
+                read_response(response)
+            }
+            Self::Url { agent, url } => {
+                tracing::debug!(%url, "Fetching disposal dates");
+                read_response(
+                    agent
+                        .get(url.as_str())
+                        .call()
+                        .map_err(GarbageError::Request)?,
+                )
             }
             Self::File(Some(path)) => {
-                tracing::debug!("Reading from file: {}", path.display());
-                serde_json::from_reader(
-                    std::fs::File::open(path).map_err(|err| io_error_to_string(&err, path))?,
-                )?
+                tracing::debug!(path = %path.display(), "Reading from file");
+                std::fs::read(path).map_err(|err| GarbageError::Io(io_error_with_path(&err, path)))
             }
             Self::File(None) => {
                 tracing::debug!("Reading from stdin");
-                serde_json::from_reader(std::io::stdin())?
+                let mut buf = Vec::new();
+                io::stdin()
+                    .read_to_end(&mut buf)
+                    .map_err(GarbageError::Io)?;
+                Ok(buf)
             }
+            Self::Memory(response) => serde_json::to_vec(response).map_err(GarbageError::Decode),
+        }
+    }
+}
+
+/// An agent for JSON requests to `url`, allowing plain HTTP only if `url`
+/// uses it.
+fn agent(proxy: Option<Proxy>, url: &Url) -> Agent {
+    let mut config = Agent::config_builder()
+        .https_only(url.scheme() == "https")
+        .http_status_as_error(false)
+        .accept("application/json");
+    if proxy.is_some() {
+        config = config.proxy(proxy);
+    }
+    let config = config.build();
+    tracing::debug!("Constructing HTTP agent with config: {config:?}");
+    config.into()
+}
+
+/// The body of a successful response, or [`GarbageError::Http`] with the
+/// start of the body.
+fn read_response(mut response: Response<Body>) -> Result<Vec<u8>, GarbageError> {
+    const SNIPPET_LEN: usize = 200;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.body_mut().read_to_string().unwrap_or_default();
+        return Err(GarbageError::Http {
+            status: status.as_u16(),
+            body: body.trim().chars().take(SNIPPET_LEN).collect(),
+        });
+    }
+    response
+        .body_mut()
+        .read_to_vec()
+        .map_err(GarbageError::Request)
+}
+
+/// Parse JSON from `reader`, ignoring a leading UTF-8 byte order mark.
+///
+/// # Errors
+///
+/// [`GarbageError::Decode`] if the JSON is invalid, otherwise
+/// [`GarbageError::Io`].
+pub fn from_json_reader<T: DeserializeOwned>(reader: impl Read) -> Result<T, GarbageError> {
+    common::from_json_reader(reader).map_err(|err| match err.downcast::<serde_json::Error>() {
+        Ok(err) => GarbageError::Decode(err),
+        Err(err) => GarbageError::Io(err),
+    })
+}
+
+/// Decode a response already parsed as JSON, such as by another HTTP
+/// client.
+///
+/// # Errors
+///
+/// Returns [`GarbageError::Decode`] if `value` is not a valid response.
+pub fn from_json_value(value: serde_json::Value) -> Result<ApiResponse, GarbageError> {
+    serde_json::from_value(value).map_err(GarbageError::Decode)
+}
+
+#[cfg(test)]
+mod test {
+    use common::mock::{serve_keep_alive, serve_once};
+    use ureq::http::StatusCode;
+
+    use super::*;
+
+    #[test]
+    fn test_file_dash_is_stdin() {
+        assert!(matches!(
+            DisposalDaysApi::file(Some("-".into())),
+            DisposalDaysApi::File(None)
+        ));
+        assert!(matches!(
+            DisposalDaysApi::file(None),
+            DisposalDaysApi::File(None)
+        ));
+        assert!(matches!(
+            DisposalDaysApi::file(Some("./-".into())),
+            DisposalDaysApi::File(Some(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_json_value() {
+        let response = from_json_value(serde_json::json!({
+            "1111": {
+                "fraction_id": "1111",
+                "fraction_name": "Matavfall",
+                "frequency": 2,
+                "dates": ["2026-02-10T00:00:00"],
+            }
+        }))
+        .unwrap();
+        assert_eq!(response["1111"].dates[0].to_string(), "2026-02-10 00:00:00");
+        assert!(matches!(
+            from_json_value(serde_json::json!([])),
+            Err(GarbageError::Decode(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_response() {
+        let fraction = GarbageFraction {
+            fraction_id: "1111".to_string(),
+            fraction_name: "Matavfall".to_string(),
+            frequency: 2,
+            dates: vec![
+                "2026-02-10T00:00:00".parse().unwrap(),
+                "2026-02-24T00:00:00".parse().unwrap(),
+            ],
+        };
+        let provider =
+            DisposalDaysApi::from_response(BTreeMap::from([("1111".to_string(), fraction)]));
+        let address: DisposalAddress = "Svingen 2".parse().unwrap();
+        let cal = provider.get_calendar(address, Options::default()).unwrap();
+        let dates: Vec<_> = cal
+            .events
+            .iter()
+            .map(|event| event.date.to_string())
+            .collect();
+        assert_eq!(dates, ["2026-02-10", "2026-02-24"]);
+        assert!(cal.events[0].summary.contains("Matavfall"));
+    }
+
+    #[test]
+    fn test_api_proxy() {
+        let proxy = Proxy::new("http://proxy.example:3128").unwrap();
+        let base_url = Url::parse(DEFAULT_BASE_URL).unwrap();
+        let DisposalDaysApi::Api { agent, .. } = DisposalDaysApi::api(Some(proxy), base_url) else {
+            panic!("Expected API provider");
+        };
+        let proxy = agent.config().proxy().unwrap();
+        assert_eq!(proxy.uri().host(), Some("proxy.example"));
+        assert_eq!(proxy.uri().port_u16(), Some(3128));
+    }
+
+    #[test]
+    fn test_fraction_order() {
+        let mut fractions = vec![
+            WasteFraction::Unknown("7".to_string(), "Farlig avfall".to_string()),
+            WasteFraction::ResidualWaste,
+            WasteFraction::GlassMetal,
+            WasteFraction::Unknown("6".to_string(), "Tekstiler".to_string()),
+            WasteFraction::FoodWaste,
+            WasteFraction::PlasticPackaging,
+            WasteFraction::PaperCardboard,
+        ];
+        fractions.sort();
+        assert_eq!(
+            fractions,
+            [
+                WasteFraction::FoodWaste,
+                WasteFraction::PaperCardboard,
+                WasteFraction::PlasticPackaging,
+                WasteFraction::GlassMetal,
+                WasteFraction::ResidualWaste,
+                WasteFraction::Unknown("6".to_string(), "Tekstiler".to_string()),
+                WasteFraction::Unknown("7".to_string(), "Farlig avfall".to_string()),
+            ]
+        );
+        assert!(WasteFraction::KNOWN.is_sorted());
+    }
+
+    #[test]
+    fn test_url() {
+        assert_eq!(
+            DisposalDaysApi::url(
+                &Url::parse(DEFAULT_BASE_URL).unwrap(),
+                &"Svingen 2 & 3".into()
+            )
+            .as_str(),
+            "https://innherredrenovasjon.no/wp-json/ir/v1/garbage-disposal-dates-by-address?address=Svingen+2+%26+3"
+        );
+        assert_eq!(
+            DisposalDaysApi::url(
+                &Url::parse("http://localhost:8080/mirror/").unwrap(),
+                &"Svingen 2".into()
+            )
+            .as_str(),
+            "http://localhost:8080/mirror/wp-json/ir/v1/garbage-disposal-dates-by-address?address=Svingen+2"
+        );
+    }
+
+    #[test]
+    fn test_agent_reused() {
+        let (base_url, server) = serve_keep_alive("{}", 2);
+        let api = DisposalDaysApi::api(None, base_url);
+        for address in ["Svingen 2", "Svingen 4"] {
+            assert_eq!(api.get_raw(&address.into()).unwrap(), b"{}");
+        }
+        assert_eq!(server.join().unwrap(), 1);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_get() {
+        let response: ApiResponse = DisposalDaysApi::from_response(ApiResponse::new())
+            .get(&"Svingen 2".into())
+            .unwrap();
+        assert!(response.is_empty());
+    }
+
+    #[test]
+    fn test_api_base_url() {
+        let (base_url, server) = serve_once("{}");
+        let raw = DisposalDaysApi::api(None, base_url)
+            .get_raw(&"Svingen 2".into())
+            .unwrap();
+        assert_eq!(raw, b"{}");
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with(
+                "GET /wp-json/ir/v1/garbage-disposal-dates-by-address?address=Svingen+2 HTTP/1.1\r\n"
+            ),
+            "{request}"
+        );
+    }
+
+    #[test]
+    fn test_from_url() {
+        let (base_url, server) = serve_once(
+            r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00"]}}"#,
+        );
+        let url = base_url.join("snapshot.json").unwrap();
+        let cal = DisposalDaysApi::from_url(None, url)
+            .get_calendar("Svingen 2".into(), Options::default())
+            .unwrap();
+        assert_eq!(cal.events.len(), 1);
+        assert!(cal.events[0].summary.contains("Matavfall"));
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /snapshot.json HTTP/1.1\r\n"),
+            "{request}"
+        );
+    }
+
+    #[test]
+    fn test_get_raw() {
+        let body = "\u{feff}{ \"1111\" : {\"fraction_id\":\"1111\"} }\n";
+        let (base_url, server) = serve_once(body);
+        let raw = DisposalDaysApi::api(None, base_url)
+            .get_raw(&"Svingen 2".into())
+            .unwrap();
+        server.join().unwrap();
+        assert_eq!(raw, body.as_bytes());
+    }
+
+    #[test]
+    fn test_json_with_bom() {
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00"]}}"#;
+        let with_bom = format!("\u{feff}{json}");
+        let response: ApiResponse = from_json_reader(with_bom.as_bytes()).unwrap();
+        assert_eq!(response["1111"].dates.len(), 1);
+        let response: ApiResponse = from_json_reader(json.as_bytes()).unwrap();
+        assert_eq!(response["1111"].dates.len(), 1);
+        assert!(matches!(
+            from_json_reader::<ApiResponse>(b"{".as_slice()),
+            Err(GarbageError::Decode(_))
+        ));
+    }
+
+    fn response(status: StatusCode, body: &str) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .body(Body::builder().data(body))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_read_response() {
+        let html = format!("<html><body>{}</body></html>", "x".repeat(500));
+        let err = read_response(response(StatusCode::INTERNAL_SERVER_ERROR, &html)).unwrap_err();
+        let GarbageError::Http { status: 500, body } = err else {
+            panic!("Expected HTTP error, got {err:?}");
         };
-        Ok(response)
+        assert!(body.starts_with("<html><body>xxx"));
+        assert_eq!(body.len(), 200);
+
+        let body = read_response(response(StatusCode::OK, &html)).unwrap();
+        assert_eq!(body, html.as_bytes());
     }
 }
@@ -1,46 +1,420 @@
 //! Create iCalendar file for Innherred Renovasjon garbage pickup dates.
-use core::{convert::Infallible, error::Error};
+use core::{error::Error, num::NonZeroUsize};
 use std::{
-    fs::File,
+    fs,
     io::{self, Write, stdout},
-    path::{Path, PathBuf},
+    path::PathBuf,
 };
+extern crate alloc;
+use alloc::collections::{BTreeMap, BTreeSet};
 
+use ::calendar::RenderOptions;
+use chrono::NaiveDate;
 use clap::{Parser as ClapParser, Subcommand, ValueEnum};
+use common::{
+    FormatArg, Output, OutputFormat,
+    args::{parse_http_url, parse_mode},
+    io_error_with_path, write_each,
+};
 
-use crate::ir_client::{DisposalAddress, schedule::DisposalDaysApi};
+use crate::ir_client::schedule::{DEFAULT_BASE_URL, WasteFraction, from_json_reader};
 
 pub(crate) mod calendar;
+pub(crate) mod geocode;
 pub(crate) mod ir_client;
 
-#[inline]
-#[must_use]
-pub(crate) fn io_error_to_string(err: &io::Error, path: &Path) -> String {
-    format!("{err}: {}", path.display())
+pub use crate::calendar::{Options, SortOrder, UidScope, infer_weekly_interval};
+#[cfg(feature = "geocode")]
+pub use crate::geocode::Nominatim;
+pub use crate::geocode::{GeocodeError, Geocoder, NoGeocoder, attach_geo, lookup_geo};
+pub use crate::ir_client::schedule::{
+    ApiResponse, DisposalDaysApi, GarbageFraction, from_json_value,
+};
+pub use crate::ir_client::{DisposalAddress, GarbageError, InvalidAddress};
+
+/// Version of the build, added to `PRODID` of the generated calendars.
+pub const VERSION: &str = common::version!();
+
+fn address_parser(value: &str) -> Result<DisposalAddress, String> {
+    value.parse().map_err(|err: InvalidAddress| err.to_string())
+}
+
+/// Accept `mailto:local@domain`.
+fn attendee_parser(value: &str) -> Result<String, String> {
+    let valid = value.strip_prefix("mailto:").is_some_and(|address| {
+        address.split_once('@').is_some_and(|(local, domain)| {
+            !local.is_empty()
+                && !domain.is_empty()
+                && !domain.contains('@')
+                && !address.contains(|c: char| c.is_whitespace() || ";:,\"<>".contains(c))
+        })
+    });
+    if valid {
+        Ok(value.to_string())
+    } else {
+        Err("Expected mailto:name@example.com".to_string())
+    }
+}
+
+/// Accept `ID=URL` with an http(s) URL.
+fn fraction_url_parser(value: &str) -> Result<(String, url::Url), String> {
+    let (id, url) = value
+        .split_once('=')
+        .ok_or("Expected fraction id and URL as ID=URL")?;
+    let url = url::Url::parse(url).map_err(|err| format!("{err}: {url}"))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!("Expected an http or https URL: {url}"));
+    }
+    Ok((id.trim().to_string(), url))
 }
 
-#[allow(clippy::unnecessary_wraps)]
-fn address_parser(value: &str) -> Result<DisposalAddress, Infallible> {
-    Ok(value.into())
+/// Accept `ID=DURATION`, with a duration as for `--reminder`.
+fn fraction_reminder_parser(value: &str) -> Result<(String, chrono::Duration), String> {
+    let (id, reminder) = value
+        .split_once('=')
+        .ok_or("Expected fraction id and duration as ID=DURATION")?;
+    Ok((id.trim().to_string(), ::calendar::parse_reminder(reminder)?))
 }
 
-#[derive(Debug, Clone, ValueEnum)]
-enum OutputFormat {
-    Ical,
-    Json,
+/// Accept a fraction id, as listed by the `fractions` command.
+fn fraction_parser(value: &str) -> Result<String, String> {
+    let id = value.trim();
+    if id.is_empty() || !id.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(format!("Expected a numeric fraction id: {value}"));
+    }
+    Ok(id.to_string())
+}
+
+fn proxy_parser(value: &str) -> Result<ureq::Proxy, String> {
+    ureq::Proxy::new(value).map_err(|err| err.to_string())
+}
+
+/// Parse an http(s) base URL, adding a trailing `/` so paths are appended
+/// to it.
+fn base_url_parser(value: &str) -> Result<url::Url, String> {
+    let mut url = parse_http_url(value)?;
+    if !url.path().ends_with('/') {
+        url.set_path(&format!("{}/", url.path()));
+    }
+    Ok(url)
+}
+
+/// Shape of the JSON output.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum GroupArg {
+    /// The response as received
+    None,
+    /// Dates by month, as `YYYY-MM`
+    Month,
+    /// Dates by fraction name
+    Fraction,
+}
+
+impl GroupArg {
+    /// Collect the dates in `response` under the key for each date, or
+    /// `None` when the response is written as received.
+    fn group(self, response: &ApiResponse) -> Option<BTreeMap<String, BTreeSet<NaiveDate>>> {
+        let key: fn(&WasteFraction, NaiveDate) -> String = match self {
+            Self::None => return None,
+            Self::Month => |_, date| date.format("%Y-%m").to_string(),
+            Self::Fraction => |fraction, _| fraction.name(),
+        };
+        let mut groups = BTreeMap::<_, BTreeSet<_>>::new();
+        for garbage_fraction in response.values() {
+            let fraction = WasteFraction::from(garbage_fraction);
+            for date in garbage_fraction
+                .dates
+                .iter()
+                .map(chrono::NaiveDateTime::date)
+            {
+                groups.entry(key(&fraction, date)).or_default().insert(date);
+            }
+        }
+        Some(groups)
+    }
 }
 
 #[derive(ClapParser, Debug)]
+#[allow(clippy::struct_excessive_bools)]
+#[command(group(
+    clap::ArgGroup::new("reminders")
+        .args(["reminder", "fraction_reminders"])
+        .multiple(true)
+))]
+#[command(group(clap::ArgGroup::new("destination").args(["output", "output_dir"])))]
 pub struct CalendarArgs {
     #[arg(long, value_parser = address_parser)]
     /// Address
     address: DisposalAddress,
     #[arg(long)]
-    /// File path, print to stdout if omitted
+    /// File path, print to stdout if omitted or `-`
     output: Option<PathBuf>,
-    /// Output format
-    #[arg(value_enum, long, default_value_t = OutputFormat::Ical)]
-    format: OutputFormat,
+    #[arg(long, value_name = "DIR")]
+    /// Directory to write the address, with the extension of each format,
+    /// to. Spaces and punctuation in the address become `-`
+    output_dir: Option<PathBuf>,
+    #[arg(long, requires = "destination")]
+    /// Do nothing if the output files are newer than this file
+    skip_if_newer_than: Option<PathBuf>,
+    #[arg(long, value_name = "OCTAL", requires = "destination", value_parser = parse_mode)]
+    /// Unix file mode of the output files, such as 0644
+    chmod: Option<u32>,
+    #[arg(long, value_name = "PATH")]
+    /// Also write the response body, as received, to this file
+    dump_raw: Option<PathBuf>,
+    /// Output format, `all` needs `--output-dir` to write a file per format
+    #[arg(value_enum, long, default_value_t = FormatArg::Ical)]
+    format: FormatArg,
+    /// Group dates in the JSON output
+    #[arg(value_enum, long, default_value_t = GroupArg::None)]
+    group: GroupArg,
+    #[arg(long)]
+    /// Omit non-standard X- properties
+    strict_rfc: bool,
+    #[arg(long)]
+    /// Only print VEVENT components, without the VCALENDAR wrapper
+    events_only: bool,
+    #[arg(long)]
+    /// Transliterate æøå and drop other non-ASCII characters in calendar and event text
+    ascii: bool,
+    #[arg(long)]
+    /// Truncate event summaries longer than this many characters
+    max_summary_len: Option<NonZeroUsize>,
+    #[arg(long)]
+    /// Omit DTEND on single-day events
+    no_dtend: bool,
+    #[arg(long)]
+    /// Omit optional properties, like DESCRIPTION, URL and X- properties,
+    /// for a smaller calendar
+    compact: bool,
+    #[arg(long, value_enum, value_name = "PROPERTY")]
+    /// Leave out an optional property for clients that reject it, can be
+    /// repeated
+    omit: Vec<::calendar::OmitProperty>,
+    #[arg(long, value_name = "VALUE", default_value = ::calendar::DEFAULT_CALSCALE, value_parser = ::calendar::parse_calscale)]
+    /// Calendar scale written as CALSCALE
+    calscale: String,
+    #[arg(long, conflicts_with = "calscale")]
+    /// Omit CALSCALE, for tools that reject it
+    no_calscale: bool,
+    #[arg(long, value_name = "STR", default_value = "")]
+    /// Text added before every event summary
+    summary_prefix: String,
+    #[arg(long, value_name = "STR", default_value = "")]
+    /// Text added after every event summary
+    summary_suffix: String,
+    #[arg(long)]
+    /// Write events lasting several days as one event per day
+    flatten_multiday: bool,
+    #[arg(long, value_name = "DURATION", value_parser = ::calendar::parse_reminder)]
+    /// Remind this long before every event, such as `1d` or `6h`
+    reminder: Option<chrono::Duration>,
+    #[arg(
+        long = "fraction-reminder",
+        value_name = "ID=DURATION",
+        value_parser = fraction_reminder_parser
+    )]
+    /// Remind this long before events of a fraction instead of
+    /// `--reminder`, can be repeated
+    fraction_reminders: Vec<(String, chrono::Duration)>,
+    #[arg(value_enum, long, default_value_t = ::calendar::AlarmAction::Display, requires = "reminders")]
+    /// What the reminder does
+    reminder_action: ::calendar::AlarmAction,
+    #[arg(long)]
+    /// Leave the tool version out of PRODID
+    no_version_stamp: bool,
+    #[arg(long)]
+    /// Check the calendar for empty UIDs or summaries and bad dates, and
+    /// fail instead of writing it
+    validate: bool,
+    #[arg(long)]
+    /// Print iCalendar output unfolded and indented, with every line
+    /// commented out, for reading. Not valid iCalendar
+    explain: bool,
+    #[arg(long)]
+    /// Leave out events on Saturdays and Sundays
+    skip_weekends: bool,
+    #[arg(long, value_name = "WEEKDAY", value_parser = ::calendar::parse_weekday)]
+    /// Only include events on this weekday, can be repeated
+    only_weekday: Vec<chrono::Weekday>,
+    #[arg(long)]
+    /// Combine fractions collected on the same day into one event
+    merge_same_day: bool,
+    #[arg(long)]
+    /// Add pickup frequency in weeks as X-FRACTION-FREQUENCY
+    include_frequency: bool,
+    /// Event order
+    #[arg(value_enum, long, default_value_t = SortOrder::Date)]
+    sort: SortOrder,
+    /// What makes event UIDs unique, `global` lets calendars for several
+    /// addresses be merged without duplicate events
+    #[arg(value_enum, long, default_value_t = UidScope::Address)]
+    uid_scope: UidScope,
+    #[arg(long = "attendee", value_name = "MAILTO", value_parser = attendee_parser, requires = "organizer")]
+    /// Invite this `mailto:` address to every event, can be repeated.
+    /// Requires `--organizer`
+    attendees: Vec<String>,
+    #[arg(long, value_name = "MAILTO", value_parser = attendee_parser, requires = "attendees")]
+    /// Send the invitation from this `mailto:` address
+    organizer: Option<String>,
+    #[arg(long = "fraction", value_name = "ID", value_parser = fraction_parser)]
+    /// Only include this fraction, can be repeated
+    fractions: Vec<String>,
+    #[arg(long = "exclude-fraction", value_name = "ID", value_parser = fraction_parser)]
+    /// Leave out this fraction, can be repeated. Wins over `--fraction`
+    exclude_fractions: Vec<String>,
+    #[arg(long = "fraction-url", value_name = "ID=URL", value_parser = fraction_url_parser)]
+    /// Link events of a fraction to this URL, can be repeated
+    fraction_urls: Vec<(String, url::Url)>,
+    #[arg(long)]
+    /// Print the number of events to stderr
+    print_count: bool,
+    #[arg(long)]
+    /// Fail if no fractions are found for the address
+    fail_on_empty: bool,
+    #[arg(long)]
+    /// Add the coordinates of the address to events as GEO, looked up with
+    /// Nominatim. Requires the `geocode` feature
+    geocode: bool,
+}
+
+impl CalendarArgs {
+    fn render_options(&self) -> RenderOptions {
+        RenderOptions {
+            strict_rfc: self.strict_rfc,
+            events_only: self.events_only,
+            ascii: self.ascii,
+            max_summary_len: self.max_summary_len,
+            no_dtend: self.no_dtend,
+            wrap_description: None,
+            compact: self.compact,
+            omit: self.omit.iter().copied().collect(),
+        }
+    }
+
+    fn options(&self) -> Options {
+        Options {
+            merge_same_day: self.merge_same_day,
+            include_frequency: self.include_frequency,
+            sort: self.sort,
+            uid_scope: self.uid_scope,
+            fraction_urls: self.fraction_urls.iter().cloned().collect(),
+            reminder: self.reminder,
+            fraction_reminders: self.fraction_reminders.iter().cloned().collect(),
+            reminder_action: self.reminder_action,
+        }
+    }
+
+    /// Where to write the calendar.
+    fn output(&self) -> Output {
+        self.output_dir.as_ref().map_or_else(
+            || Output::new(self.output.as_deref()),
+            |dir| {
+                let name: String = self
+                    .address
+                    .to_string()
+                    .chars()
+                    .map(|c| if c.is_alphanumeric() { c } else { '-' })
+                    .collect();
+                Output::in_dir(dir, &name)
+            },
+        )
+    }
+
+    /// How to look up coordinates for the address, through `proxy` if
+    /// given.
+    fn geocoder(&self, proxy: Option<ureq::Proxy>) -> Box<dyn Geocoder> {
+        #[cfg(feature = "geocode")]
+        if self.geocode {
+            return Box::new(Nominatim::new(
+                proxy,
+                url::Url::parse(Nominatim::DEFAULT_BASE_URL).expect("Should never happen"),
+            ));
+        }
+        #[cfg(not(feature = "geocode"))]
+        drop(proxy);
+        #[cfg(not(feature = "geocode"))]
+        if self.geocode {
+            tracing::warn!("Built without the geocode feature, leaving out GEO");
+        }
+        Box::new(NoGeocoder)
+    }
+
+    /// Build the calendar for `response`, with `geo` on every event.
+    fn calendar(&self, response: ApiResponse, geo: Option<(f64, f64)>) -> ::calendar::Calendar {
+        let mut cal = DisposalDaysApi::calendar(self.address.clone(), response, self.options());
+        cal.options = self.render_options();
+        cal.calscale = (!self.no_calscale).then(|| self.calscale.clone());
+        cal.attendees.clone_from(&self.attendees);
+        cal.organizer.clone_from(&self.organizer);
+        cal.retain_weekdays(&self.only_weekday, self.skip_weekends);
+        cal.decorate_summaries(&self.summary_prefix, &self.summary_suffix);
+        if !self.no_version_stamp {
+            cal.stamp_version(VERSION);
+        }
+        for event in &mut cal.events {
+            event.geo = geo;
+        }
+        if self.flatten_multiday {
+            cal.flatten_multiday();
+        }
+        cal
+    }
+
+    /// Whether the fraction with `id` passes `--fraction` and
+    /// `--exclude-fraction`.
+    fn keep_fraction(&self, id: &str) -> bool {
+        let listed = |ids: &[String]| ids.iter().any(|listed| listed == id);
+        !listed(&self.exclude_fractions) && (self.fractions.is_empty() || listed(&self.fractions))
+    }
+
+    /// Render `response` in each output format, with `geo` on every event,
+    /// and count the calendar events.
+    fn render(
+        &self,
+        response: &serde_json::Value,
+        geo: Option<(f64, f64)>,
+    ) -> Result<(Vec<String>, usize), Box<dyn Error>> {
+        let mut parsed = from_json_value(response.clone())?;
+        parsed.retain(|_, fraction| self.keep_fraction(&fraction.fraction_id));
+        let groups = self.group.group(&parsed);
+        let cal = self.calendar(parsed.clone(), geo);
+        if self.validate {
+            cal.is_valid().map_err(|problems| problems.join("; "))?;
+        }
+        let mut outputs = Vec::new();
+        for format in self.format.formats() {
+            outputs.push(match format {
+                OutputFormat::Ical => {
+                    if self.events_only {
+                        tracing::warn!("Output is not a standalone calendar");
+                    }
+                    if self.explain {
+                        tracing::warn!("Output is a debug view, not iCalendar");
+                        ::calendar::explain(&cal.to_string())
+                    } else {
+                        cal.to_string()
+                    }
+                }
+
+                OutputFormat::Json => match &groups {
+                    Some(groups) => serde_json::to_string(groups)?,
+                    None => serde_json::to_string(&retain_received(response, &parsed))?,
+                },
+            });
+        }
+        Ok((outputs, cal.events.len()))
+    }
+}
+
+/// `response` as received, with only the fractions kept in `parsed`, so
+/// fields that [`ApiResponse`] does not model are written too.
+fn retain_received(response: &serde_json::Value, parsed: &ApiResponse) -> serde_json::Value {
+    let mut response = response.clone();
+    if let Some(fractions) = response.as_object_mut() {
+        fractions.retain(|id, _| parsed.contains_key(id));
+    }
+    response
 }
 
 #[derive(Subcommand, Debug)]
@@ -49,62 +423,791 @@ pub enum Commands {
     Api {
         #[clap(flatten)]
         args: CalendarArgs,
+        /// Proxy URL, overrides `ALL_PROXY`, `HTTPS_PROXY` and `HTTP_PROXY`
+        #[arg(long, value_parser = proxy_parser)]
+        proxy: Option<ureq::Proxy>,
+        /// Print the request URL and exit without fetching
+        #[arg(long)]
+        print_url: bool,
+        /// API base URL, for a mirror or a mock server
+        #[arg(long, env = "GARBAGE_API_BASE_URL", value_parser = base_url_parser, default_value = DEFAULT_BASE_URL)]
+        api_base_url: url::Url,
     },
     /// Get delivery dates from JSON file
     File {
         #[clap(flatten)]
         args: CalendarArgs,
-        /// File path, read from stdin of omitted
+        /// File path, read from stdin if omitted or `-`
         input: Option<PathBuf>,
+        /// Fetch the JSON from an http(s) URL instead of a file
+        #[arg(long, conflicts_with = "input", value_parser = parse_http_url)]
+        input_url: Option<url::Url>,
+        /// Proxy URL for `--input-url` and geocoding, overrides `ALL_PROXY`,
+        /// `HTTPS_PROXY` and `HTTP_PROXY`
+        #[arg(long, value_parser = proxy_parser)]
+        proxy: Option<ureq::Proxy>,
     },
+    /// List the known waste fractions
+    Fractions,
+    /// Render a calendar from bundled data to check that it works offline
+    Selftest,
+}
+
+/// Write the id, icon and name of each known fraction.
+fn write_fractions(mut out: impl Write) -> Result<(), io::Error> {
+    for fraction in WasteFraction::KNOWN {
+        writeln!(
+            out,
+            "{}\t{} {}",
+            fraction.get_id(),
+            fraction.icon(),
+            fraction.name()
+        )?;
+    }
+    Ok(())
+}
+
+/// API response used by `selftest`.
+const SELFTEST_INPUT: &str = include_str!("selftest.json");
+
+/// Render the calendar for [`SELFTEST_INPUT`] and check it, without any
+/// network or file access.
+fn selftest_calendar() -> Result<String, Box<dyn Error>> {
+    let args = CalendarArgs::try_parse_from(["selftest", "--address", "Svingen 2"])?;
+    let response: serde_json::Value = from_json_reader(SELFTEST_INPUT.as_bytes())?;
+    let (outputs, count) = args.render(&response, None)?;
+    Ok(::calendar::check_selftest(&outputs, count)?)
 }
 
 impl Commands {
     #[allow(clippy::missing_panics_doc)]
     #[allow(clippy::missing_errors_doc)]
     pub fn run(self) -> Result<(), Box<dyn Error>> {
-        let (endpoint, args) = match self {
-            Self::Api { args } => (DisposalDaysApi::api(), args),
-            Self::File { input, args } => (DisposalDaysApi::file(input), args),
-        };
-
-        let output = match args.format {
-            OutputFormat::Ical => {
-                let cal = endpoint.get_calendar(args.address)?;
+        if let Some(warning) =
+            common::stdin_unused_warning(self.input_source().as_deref(), common::stdin_is_piped())
+        {
+            tracing::warn!("{warning}");
+        }
+        self.run_with_stderr(&mut io::stderr())
+    }
 
-                match args.output {
-                    Some(path) => {
-                        let file =
-                            File::create(&path).map_err(|err| io_error_to_string(&err, &path))?;
-                        cal.write(file)
-                            .map_err(|err| io_error_to_string(&err, &path))?;
-                    }
+    /// The file or URL the `file` command reads instead of stdin, if any.
+    fn input_source(&self) -> Option<String> {
+        match self {
+            Self::File {
+                input_url: Some(url),
+                ..
+            } => Some(url.to_string()),
+            Self::File {
+                input: Some(path), ..
+            } if path.as_os_str() != "-" && path.as_os_str() != "/dev/stdin" => {
+                Some(path.display().to_string())
+            }
+            _ => None,
+        }
+    }
 
-                    None => {
-                        cal.write(stdout())?;
-                    }
+    /// Run the command, writing the `--print-count` output to `stderr`.
+    fn run_with_stderr(self, stderr: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+        let (endpoint, args, proxy) = match self {
+            Self::Api {
+                args,
+                proxy,
+                print_url,
+                api_base_url,
+            } => {
+                if print_url {
+                    let url = DisposalDaysApi::url(&api_base_url, &args.address);
+                    writeln!(stdout(), "{url}")?;
+                    return Ok(());
                 }
-                return Ok(());
+                (
+                    DisposalDaysApi::api(proxy.clone(), api_base_url),
+                    args,
+                    proxy,
+                )
             }
-
-            OutputFormat::Json => {
-                let response: serde_json::Value = endpoint.get(&args.address)?;
-                tracing::debug!("Got: {response:?}");
-                serde_json::to_string(&response)?
+            Self::File {
+                input_url: Some(url),
+                args,
+                proxy,
+                ..
+            } => (DisposalDaysApi::from_url(proxy.clone(), url), args, proxy),
+            Self::File {
+                input, args, proxy, ..
+            } => (DisposalDaysApi::file(input), args, proxy),
+            Self::Fractions => return Ok(write_fractions(stdout().lock())?),
+            Self::Selftest => {
+                selftest_calendar()?;
+                writeln!(stdout(), "OK")?;
+                return Ok(());
             }
         };
 
-        match args.output {
-            Some(path) => {
-                // Try to create file before we do any network requests
-                let mut file =
-                    File::create(&path).map_err(|err| io_error_to_string(&err, &path))?;
-                write!(file, "{output}").map_err(|err| io_error_to_string(&err, &path))?;
+        let output = args.output();
+        let formats = args.format.formats();
+        if let Some(trigger) = &args.skip_if_newer_than
+            && output.is_newer(formats, trigger)
+        {
+            tracing::info!(
+                ?output,
+                trigger = %trigger.display(),
+                "Output is up to date, skipping"
+            );
+            return Ok(());
+        }
+
+        // Create the output files before we do any network requests
+        let writers = output.create_each(formats)?;
+
+        let raw = endpoint.get_raw(&args.address)?;
+        if let Some(path) = &args.dump_raw {
+            fs::write(path, &raw).map_err(|err| io_error_with_path(&err, path))?;
+        }
+        let response: serde_json::Value = from_json_reader(raw.as_slice())?;
+        tracing::debug!("Got: {response:?}");
+        if response.as_object().is_some_and(serde_json::Map::is_empty) {
+            let err = GarbageError::NoFractions(args.address.clone());
+            if args.fail_on_empty {
+                return Err(err.into());
             }
+            tracing::warn!("{err}");
+        }
+        let geo = lookup_geo(args.geocoder(proxy).as_ref(), &args.address);
+        let (outputs, count) = args.render(&response, geo)?;
 
-            None => stdout().write_fmt(format_args!("{output}"))?,
+        write_each(writers, formats, outputs, args.chmod)?;
+        if args.print_count {
+            writeln!(stderr, "{count}")?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use core::sync::atomic::Ordering;
+    use std::{env, path::Path};
+
+    use super::*;
+
+    #[derive(ClapParser)]
+    struct Cli {
+        #[command(subcommand)]
+        command: Commands,
+    }
+
+    fn run(args: &[&str]) -> Result<(), Box<dyn Error>> {
+        Cli::try_parse_from(args)?
+            .command
+            .run_with_stderr(&mut io::stderr())
+    }
+
+    /// A fresh directory for a test, removed by the caller.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("garbage-test-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Render the disposal dates in `json` with extra arguments.
+    fn render(name: &str, json: &str, extra: &[&str]) -> String {
+        let dir = test_dir(name);
+        let input = dir.join("input.json");
+        fs::write(&input, json).unwrap();
+        let output = dir.join("out.ics");
+        let mut args = ["garbage", "file", "--address", "Svingen 2"].to_vec();
+        args.extend(extra);
+        args.extend([
+            "--output",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        run(&args).unwrap();
+        let output = fs::read_to_string(&output).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        output
+    }
+
+    #[test]
+    fn test_input_source() {
+        let source = |args: &[&str]| {
+            let mut argv = vec!["garbage", "file", "--address", "Svingen 2"];
+            argv.extend(args);
+            Cli::try_parse_from(argv).unwrap().command.input_source()
+        };
+        assert_eq!(source(&[]), None);
+        assert_eq!(source(&["-"]), None);
+        assert_eq!(source(&["/dev/stdin"]), None);
+        assert_eq!(source(&["input.json"]).as_deref(), Some("input.json"));
+        assert_eq!(
+            source(&["--input-url", "http://localhost/a.json"]).as_deref(),
+            Some("http://localhost/a.json")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_run_with_piped_stdin() {
+        const DIR: &str = "GARBAGE_TEST_PIPED_DIR";
+        let file = |input: &Path, output: &Path| {
+            Cli::try_parse_from([
+                "garbage",
+                "file",
+                "--address",
+                "Svingen 2",
+                "--output",
+                output.to_str().unwrap(),
+                input.to_str().unwrap(),
+            ])
+            .unwrap()
+            .command
+            .run()
+        };
+        #[allow(clippy::disallowed_methods)]
+        let child_dir = env::var_os(DIR);
+        if let Some(dir) = child_dir.map(PathBuf::from) {
+            // In the child started below, with data piped to stdin
+            file(Path::new("/dev/stdin"), &dir.join("stdin.ics")).unwrap();
+            file(&dir.join("input.json"), &dir.join("path.ics")).unwrap();
+            return;
+        }
+
+        let dir = test_dir("piped");
+        fs::write(dir.join("input.json"), SELFTEST_INPUT).unwrap();
+        let mut child = std::process::Command::new(env::current_exe().unwrap())
+            .args(["--exact", "test::test_run_with_piped_stdin"])
+            .env(DIR, &dir)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(SELFTEST_INPUT.as_bytes())
+            .unwrap();
+        assert!(child.wait().unwrap().success());
+        for output in ["stdin.ics", "path.ics"] {
+            let ics = fs::read_to_string(dir.join(output)).unwrap();
+            assert!(ics.contains("BEGIN:VEVENT"), "{output}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_fetch_leaves_no_output() {
+        let dir = test_dir("fetch");
+        let output = dir.join("out.ics");
+        let output = output.to_str().unwrap();
+        let mut args = ["garbage", "file", "--address", "Svingen 2", "--output"].to_vec();
+        args.extend([output, "/nonexistent/input.json"]);
+        assert!(run(&args).is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fail_on_empty() {
+        let dir = test_dir("empty");
+        let input = dir.join("input.json");
+        fs::write(&input, "{}").unwrap();
+        let output = dir.join("out.ics");
+        let mut args = ["garbage", "file", "--address", "Svingen 2"].to_vec();
+        args.extend([
+            "--output",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        run(&args).unwrap();
+        assert!(
+            fs::read_to_string(&output)
+                .unwrap()
+                .contains("BEGIN:VCALENDAR")
+        );
+        fs::remove_file(&output).unwrap();
+
+        args.push("--fail-on-empty");
+        let err = run(&args).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "No fractions found for address Svingen 2, check spelling"
+        );
+        assert!(!output.exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_all() {
+        let dir = test_dir("all");
+        let input = dir.join("input.json");
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00"]}}"#;
+        fs::write(&input, json).unwrap();
+        let mut args = [
+            "garbage",
+            "file",
+            "--address",
+            "Svingen 2",
+            "--format",
+            "all",
+        ]
+        .to_vec();
+        args.extend([
+            "--output-dir",
+            dir.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        run(&args).unwrap();
+        let ics = fs::read_to_string(dir.join("Svingen-2.ics")).unwrap();
+        assert!(ics.contains("SUMMARY:🍌 Matavfall tirsdag 10."));
+        let output: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("Svingen-2.json")).unwrap()).unwrap();
+        assert_eq!(output["1111"]["fraction_name"], "Matavfall");
+
+        // Up to date only if every format was written after the trigger
+        fs::remove_file(dir.join("Svingen-2.json")).unwrap();
+        let mut skip = args.clone();
+        skip.extend(["--skip-if-newer-than", input.to_str().unwrap()]);
+        run(&skip).unwrap();
+        assert!(dir.join("Svingen-2.json").exists());
+
+        let output = dir.join("out.ics");
+        let mut file = args[..6].to_vec();
+        file.extend([
+            "--output",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        let err = run(&file).unwrap_err();
+        assert!(err.to_string().starts_with("Several formats"), "{err}");
+        let both = ["--output", "out.ics", "--output-dir", "out"];
+        assert!(Cli::try_parse_from(args.iter().chain(&both)).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_all_fetches_once() {
+        let (base_url, hits) = common::mock::serve_counting(
+            r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00"]}}"#,
+        );
+        let dir = test_dir("once");
+        run(&[
+            "garbage",
+            "api",
+            "--api-base-url",
+            base_url.as_str(),
+            "--address",
+            "Svingen 2",
+            "--format",
+            "all",
+            "--output-dir",
+            dir.to_str().unwrap(),
+        ])
+        .unwrap();
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(dir.join("Svingen-2.ics").exists());
+        assert!(dir.join("Svingen-2.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn group_json(name: &str, group: &str) -> serde_json::Value {
+        let json = r#"{
+            "1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00","2026-02-24T00:00:00","2026-03-10T00:00:00"]},
+            "9992":{"fraction_id":"9992","fraction_name":"Restavfall","frequency":4,"dates":["2026-02-24T00:00:00","2026-03-24T00:00:00"]}
+        }"#;
+        let output = render(name, json, &["--format", "json", "--group", group]);
+        serde_json::from_str(&output).unwrap()
+    }
+
+    #[test]
+    fn test_group_by_month() {
+        assert_eq!(
+            group_json("group-month", "month"),
+            serde_json::json!({
+                "2026-02": ["2026-02-10", "2026-02-24"],
+                "2026-03": ["2026-03-10", "2026-03-24"],
+            })
+        );
+    }
+
+    #[test]
+    fn test_group_by_fraction() {
+        assert_eq!(
+            group_json("group-fraction", "fraction"),
+            serde_json::json!({
+                "Matavfall": ["2026-02-10", "2026-02-24", "2026-03-10"],
+                "Restavfall": ["2026-02-24", "2026-03-24"],
+            })
+        );
+        assert_eq!(
+            group_json("group-none", "none")["1111"]["fraction_name"],
+            "Matavfall"
+        );
+    }
+
+    #[test]
+    fn test_json_keeps_unknown_fields() {
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"frequency_human":"hver 2. uke","dates":["2026-02-10T00:00:00"],"extra":{"a":[1]}}}"#;
+        let output: serde_json::Value =
+            serde_json::from_str(&render("unknown", json, &["--format", "json"])).unwrap();
+        assert_eq!(
+            output,
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_attendees() {
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00"]}}"#;
+        let ics = render(
+            "attendee",
+            json,
+            &[
+                "--attendee",
+                "mailto:kari@example.com",
+                "--attendee",
+                "mailto:ola@example.com",
+                "--organizer",
+                "mailto:per@example.com",
+            ],
+        );
+        assert!(ics.contains("\r\nMETHOD:REQUEST\r\n"));
+        assert!(ics.contains("\r\nORGANIZER;CN=per@example.com:mailto:per@example.com\r\n"));
+        assert!(
+            ics.contains("\r\nATTENDEE;CN=kari@example.com;RSVP=FALSE:mailto:kari@example.com\r\n")
+        );
+        assert!(
+            ics.contains("\r\nATTENDEE;CN=ola@example.com;RSVP=FALSE:mailto:ola@example.com\r\n")
+        );
+
+        assert!(attendee_parser("kari@example.com").is_err());
+        assert!(attendee_parser("mailto:kari").is_err());
+        assert!(attendee_parser("mailto:@example.com").is_err());
+        assert!(attendee_parser("mailto:kari@example.com;RSVP=TRUE").is_err());
+        // Invitations need both
+        for extra in ["--attendee", "--organizer"] {
+            assert!(
+                CalendarArgs::try_parse_from([
+                    "garbage",
+                    "--address",
+                    "x",
+                    extra,
+                    "mailto:kari@example.com"
+                ])
+                .is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_selftest() {
+        let ics = selftest_calendar().unwrap();
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.contains("BEGIN:VEVENT\r\n"));
+        assert!(run(&["garbage", "selftest"]).is_ok());
+    }
+
+    #[test]
+    fn test_dump_raw() {
+        let dir = test_dir("raw");
+        let json = "\u{feff}{ \"1111\": {\"fraction_id\":\"1111\",\"fraction_name\":\"Matavfall\",\"frequency\":2,\"dates\":[]} }\n";
+        let raw = dir.join("raw.json");
+        let extra = ["--dump-raw", raw.to_str().unwrap(), "--format", "ical"];
+        assert!(render("raw-render", json, &extra).contains("BEGIN:VCALENDAR"));
+        assert_eq!(fs::read_to_string(&raw).unwrap(), json);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_print_count() {
+        let dir = test_dir("count");
+        let input = dir.join("input.json");
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00","2026-02-24T00:00:00"]}}"#;
+        fs::write(&input, json).unwrap();
+        let output = dir.join("out.ics");
+        let mut args = ["garbage", "file", "--address", "Svingen 2", "--print-count"].to_vec();
+        args.extend([
+            "--output",
+            output.to_str().unwrap(),
+            input.to_str().unwrap(),
+        ]);
+        let mut stderr = Vec::new();
+        Cli::try_parse_from(args)
+            .unwrap()
+            .command
+            .run_with_stderr(&mut stderr)
+            .unwrap();
+        assert_eq!(String::from_utf8(stderr).unwrap(), "2\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_fractions() {
+        let mut out = Vec::new();
+        write_fractions(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().count(), 5);
+        assert!(out.contains("1111\t🍌 Matavfall\n"));
+        assert!(run(&["garbage", "fractions"]).is_ok());
+    }
+
+    #[test]
+    fn test_skip_weekends() {
+        // Tuesday and Saturday
+        let json = r#"{"1111":{"fraction_id":"1111","fraction_name":"Matavfall","frequency":2,"dates":["2026-02-10T00:00:00","2026-02-14T00:00:00"]}}"#;
+        let ics = render("weekend", json, &["--skip-weekends"]);
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 1);
+        assert!(ics.contains("SUMMARY:🍌 Matavfall tirsdag 10."));
+    }
+
+    #[test]
+    fn test_fraction_filters() {
+        let response = serde_json::json!({
+            "1111": {"fraction_id": "1111", "fraction_name": "Matavfall", "frequency": 2, "dates": ["2026-02-10T00:00:00"]},
+            "1222": {"fraction_id": "1222", "fraction_name": "Papp/papir", "frequency": 4, "dates": ["2026-02-11T00:00:00"]},
+            "9992": {"fraction_id": "9992", "fraction_name": "Restavfall", "frequency": 4, "dates": ["2026-02-12T00:00:00"]},
+        });
+        let render = |extra: &[&str]| {
+            let mut args = vec!["garbage", "--address", "Svingen 2"];
+            args.extend(extra);
+            let (outputs, _) = CalendarArgs::try_parse_from(args)
+                .unwrap()
+                .render(&response, None)
+                .unwrap();
+            outputs.concat()
+        };
+        let paper = WasteFraction::PaperCardboard.name();
+        let food = WasteFraction::FoodWaste.name();
+        assert!(render(&[]).contains(&paper));
+
+        let ics = render(&["--exclude-fraction", "1222"]);
+        assert!(!ics.contains(&paper));
+        assert!(ics.contains(&food));
+
+        let ics = render(&["--fraction", "1222", "--fraction", "1111"]);
+        assert!(ics.contains(&paper));
+        assert!(ics.contains(&food));
+        assert!(!ics.contains(&WasteFraction::ResidualWaste.name()));
+
+        let ics = render(&["--fraction", "1222", "--exclude-fraction", "1222"]);
+        assert!(!ics.contains(&paper));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&["--format", "json", "--exclude-fraction", "1222"]))
+                .unwrap();
+        assert!(json.get("1222").is_none(), "{json}");
+        assert_eq!(json["1111"]["fraction_name"], "Matavfall");
+        assert_eq!(json["9992"]["dates"][0], "2026-02-12T00:00:00");
+        assert!(
+            CalendarArgs::try_parse_from(["garbage", "--address", "x", "--fraction", "papp"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_every_format_renders() {
+        let response: serde_json::Value = from_json_reader(SELFTEST_INPUT.as_bytes()).unwrap();
+        for format in FormatArg::value_variants() {
+            let name = format.to_possible_value().unwrap();
+            let args = CalendarArgs::try_parse_from([
+                "garbage",
+                "--address",
+                "Svingen 2",
+                "--format",
+                name.get_name(),
+            ])
+            .unwrap();
+            let (outputs, _) = args.render(&response, None).unwrap();
+            assert_eq!(outputs.len(), format.formats().len(), "{format:?}");
+            assert!(
+                outputs.iter().all(|output| !output.is_empty()),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_omit() {
+        let response: serde_json::Value = from_json_reader(SELFTEST_INPUT.as_bytes()).unwrap();
+        let render = |extra: &[&str]| {
+            let mut args = vec!["garbage", "--address", "Svingen 2"];
+            args.extend(extra);
+            let (outputs, _) = CalendarArgs::try_parse_from(args)
+                .unwrap()
+                .render(&response, None)
+                .unwrap();
+            outputs.concat()
+        };
+        let full = render(&[]);
+        let ics = render(&["--omit", "transp", "--omit", "url"]);
+        for name in ["TRANSP", "URL"] {
+            assert!(full.contains(&format!("\r\n{name}:")), "{name}");
+            assert!(!ics.contains(&format!("\r\n{name}:")), "{name}");
+        }
+        assert!(ics.contains("\r\nMETHOD:PUBLISH\r\n"));
+        assert!(
+            CalendarArgs::try_parse_from(["garbage", "--address", "x", "--omit", "dtstart"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_reminder() {
+        let response: serde_json::Value = from_json_reader(SELFTEST_INPUT.as_bytes()).unwrap();
+        let render = |extra: &[&str]| {
+            let mut args = vec!["garbage", "--address", "Svingen 2"];
+            args.extend(extra);
+            let (outputs, _) = CalendarArgs::try_parse_from(args)
+                .unwrap()
+                .render(&response, None)
+                .unwrap();
+            outputs.concat().replace("\r\n ", "")
+        };
+        let ics = render(&["--reminder", "1d"]);
+        let summary = ics
+            .lines()
+            .find_map(|line| line.strip_prefix("SUMMARY:"))
+            .unwrap();
+        let start = ics.find("BEGIN:VALARM").unwrap();
+        let end = ics.find("END:VALARM\r\n").unwrap() + "END:VALARM\r\n".len();
+        // Same rendering as postgang for the same trigger
+        assert_eq!(
+            ics[start..end],
+            format!(
+                "BEGIN:VALARM\r\nACTION:DISPLAY\r\nTRIGGER:-P1D\r\nDESCRIPTION:{summary} i morgen\r\nEND:VALARM\r\n"
+            )
+        );
+        assert!(!render(&[]).contains("VALARM"));
+        assert!(
+            CalendarArgs::try_parse_from([
+                "garbage",
+                "--address",
+                "x",
+                "--reminder-action",
+                "audio"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_fraction_reminder() {
+        let response = serde_json::json!({
+            "1111": {"fraction_id": "1111", "fraction_name": "Matavfall", "frequency": 2, "dates": ["2026-02-10T00:00:00"]},
+            "5": {"fraction_id": "5", "fraction_name": "Glass- og metallemballasje", "frequency": 8, "dates": ["2026-02-10T00:00:00"]},
+        });
+        let render = |extra: &[&str]| {
+            let mut args = vec!["garbage", "--address", "Svingen 2", "--reminder", "3d"];
+            args.extend(extra);
+            let (outputs, _) = CalendarArgs::try_parse_from(args)
+                .unwrap()
+                .render(&response, None)
+                .unwrap();
+            outputs.concat()
+        };
+        let triggers = |ics: &str| {
+            ics.lines()
+                .filter_map(|line| line.strip_prefix("TRIGGER:"))
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+        // Food waste comes first
+        let ics = render(&["--fraction-reminder", "5=5d"]);
+        assert_eq!(triggers(&ics), ["-P3D", "-P5D"]);
+        let ics = render(&["--fraction-reminder", "5=5d", "--merge-same-day"]);
+        assert_eq!(triggers(&ics), ["-P5D"]);
+        assert_eq!(triggers(&render(&[])), ["-P3D", "-P3D"]);
+
+        let (id, reminder) = fraction_reminder_parser("5=5d").unwrap();
+        assert_eq!(id, "5");
+        assert_eq!(reminder, chrono::Duration::days(5));
+        assert!(fraction_reminder_parser("5").is_err());
+        assert!(fraction_reminder_parser("5=5w").is_err());
+        assert!(
+            CalendarArgs::try_parse_from([
+                "garbage",
+                "--address",
+                "x",
+                "--fraction-reminder",
+                "5=1d",
+                "--reminder-action",
+                "audio"
+            ])
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_fraction_url_parser() {
+        let (id, url) = fraction_url_parser("1111=https://example.com/mat").unwrap();
+        assert_eq!(id, "1111");
+        assert_eq!(url.as_str(), "https://example.com/mat");
+        assert!(fraction_url_parser("1111").is_err());
+        assert!(fraction_url_parser("1111=example.com").is_err());
+        assert!(fraction_url_parser("1111=ftp://example.com/").is_err());
+    }
+
+    #[test]
+    fn test_base_url_parser() {
+        assert_eq!(
+            base_url_parser("http://localhost:8080/mirror")
+                .unwrap()
+                .as_str(),
+            "http://localhost:8080/mirror/"
+        );
+        assert_eq!(
+            base_url_parser(DEFAULT_BASE_URL).unwrap().as_str(),
+            DEFAULT_BASE_URL
+        );
+        assert!(base_url_parser("ftp://example.com/").is_err());
+        assert!(base_url_parser("example.com").is_err());
+    }
+
+    #[test]
+    fn test_input_url() {
+        let parse = |args: &[&str]| {
+            Cli::try_parse_from(
+                ["garbage", "file", "--address", "Svingen 2"]
+                    .iter()
+                    .chain(args),
+            )
+        };
+        let cli = parse(&["--input-url", "http://localhost:8080/snapshot.json"]).unwrap();
+        let Commands::File { input_url, .. } = cli.command else {
+            panic!("Expected file command");
+        };
+        assert_eq!(
+            input_url.unwrap().as_str(),
+            "http://localhost:8080/snapshot.json"
+        );
+        assert!(parse(&["--input-url", "file:///tmp/snapshot.json"]).is_err());
+        assert!(parse(&["--input-url", "http://localhost/a.json", "input.json"]).is_err());
+        let cli = parse(&["--proxy", "http://proxy.example:3128"]).unwrap();
+        let Commands::File { proxy, .. } = cli.command else {
+            panic!("Expected file command");
+        };
+        assert_eq!(proxy.unwrap().uri().host(), Some("proxy.example"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_chmod() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = test_dir("chmod");
+        let input = dir.join("input.json");
+        fs::write(&input, "{}").unwrap();
+        let output = dir.join("out.ics");
+        for mode in ["0600", "644"] {
+            let mut args = ["garbage", "file", "--address", "Svingen 2"].to_vec();
+            args.extend(["--chmod", mode, "--output", output.to_str().unwrap()]);
+            args.push(input.to_str().unwrap());
+            run(&args).unwrap();
+            let actual = fs::metadata(&output).unwrap().permissions().mode() & 0o7777;
+            assert_eq!(actual, u32::from_str_radix(mode, 8).unwrap());
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,24 +1,27 @@
 //! Mailbox delivery dates API.
 
 use core::fmt::Debug;
-use std::path::PathBuf;
+use std::{io::Read, path::PathBuf};
 
 use chrono::{NaiveDate, Utc};
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use ureq::middleware::Middleware;
 use ureq::{
-    Agent, Body, SendBody,
+    Agent, Body, Proxy, SendBody,
+    config::ConfigBuilder,
     http::{Request, Response, header::HeaderValue},
     middleware::MiddlewareNext,
+    typestate::AgentScope,
 };
 use url::Url;
-use uuid::Uuid;
+
+use common::from_json_reader;
 
 use crate::bring_client::{HEADER_KEY, HEADER_UID};
 use crate::calendar::Calendar;
 use crate::{
-    bring_client::{ApiKey, ApiUid, NORWAY, NorwegianPostalCode},
+    bring_client::{ApiKey, ApiUid, CountryCode, NorwegianPostalCode},
     io_error_to_string,
 };
 
@@ -40,43 +43,187 @@ impl Middleware for AuthMiddleware {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
+#[serde(try_from = "RawApiResponse")]
 /// Represents JSON structure from the API.
+///
+/// Accepts both `delivery_dates` and `deliveryDates`, as the API has used
+/// both spellings.
 pub struct ApiResponse {
     pub delivery_dates: Vec<NaiveDate>,
 }
 
+#[derive(Deserialize)]
+struct RawApiResponse {
+    delivery_dates: Option<Vec<NaiveDate>>,
+    #[serde(rename = "deliveryDates")]
+    delivery_dates_camel_case: Option<Vec<NaiveDate>>,
+}
+
+impl TryFrom<RawApiResponse> for ApiResponse {
+    type Error = &'static str;
+
+    fn try_from(value: RawApiResponse) -> Result<Self, Self::Error> {
+        let delivery_dates = match (value.delivery_dates, value.delivery_dates_camel_case) {
+            (Some(dates), camel_case) => {
+                if camel_case.is_some() {
+                    tracing::warn!(
+                        "Got both delivery_dates and deliveryDates, using delivery_dates"
+                    );
+                }
+                tracing::debug!("Got delivery_dates");
+                dates
+            }
+            (None, Some(dates)) => {
+                tracing::debug!("Got deliveryDates");
+                dates
+            }
+            (None, None) => return Err("missing field `delivery_dates`"),
+        };
+        Ok(Self { delivery_dates })
+    }
+}
+
+/// Decode a response already parsed as JSON, such as by another HTTP
+/// client.
+impl TryFrom<serde_json::Value> for ApiResponse {
+    type Error = serde_json::Error;
+
+    fn try_from(value: serde_json::Value) -> Result<Self, Self::Error> {
+        serde_json::from_value(value)
+    }
+}
+
+/// A mailbox delivery date for a postal code.
+///
+/// Ordered by date, then postal code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeliveryDate {
+    pub date: NaiveDate,
+    pub postal_code: NorwegianPostalCode,
+}
+
+impl DeliveryDate {
+    #[must_use]
+    pub const fn new(postal_code: NorwegianPostalCode, date: NaiveDate) -> Self {
+        Self { date, postal_code }
+    }
+}
+
+/// The Bring API instance and query to ask for delivery dates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiEndpoint {
+    /// API base URL, see [`ApiEndpoint::DEFAULT_BASE_URL`]
+    pub base_url: Url,
+    /// Country of the postal codes
+    pub country: CountryCode,
+    /// Postal code of the sender, sent as the `from` query parameter
+    pub from_code: Option<NorwegianPostalCode>,
+}
+
+impl ApiEndpoint {
+    /// The Bring address API.
+    pub const DEFAULT_BASE_URL: &str = "https://api.bring.com/address/api/";
+}
+
+impl Default for ApiEndpoint {
+    fn default() -> Self {
+        Self {
+            base_url: Url::parse(Self::DEFAULT_BASE_URL).expect("Should never happen"),
+            country: CountryCode::NORWAY,
+            from_code: None,
+        }
+    }
+}
+
 /// Delivery day provider.
 pub enum DeliveryDays {
     /// Fetches JSON from [Bring API](https://developer.bring.com/api/postal-code/#get-mailbox-delivery-dates-at-postal-code-get).
     // https://api.bring.com/address/api/{country-code}/postal-codes/{postal-code}/mailbox-delivery-dates
-    Api(Agent),
+    ///
+    /// The agent is built once and reused for every request made through
+    /// this provider, so its connection pool is shared between calls.
+    Api { agent: Agent, endpoint: ApiEndpoint },
+
+    /// Fetches JSON from any URL, such as a cache of API responses.
+    Url { agent: Agent, url: Url },
 
     /// Reads JSON from a file.
     File(Option<PathBuf>),
+
+    /// Serves a response held in memory, for tests and embedders with
+    /// their own data.
+    Memory(ApiResponse),
 }
 
 impl DeliveryDays {
-    /// Read dates from REST API.
+    /// Read dates for postal codes in `country` from the Bring API.
+    ///
+    /// Without an explicit `proxy`, the proxy is read from the environment.
+    #[must_use]
+    pub fn api(
+        api_key: ApiKey,
+        api_uid: ApiUid,
+        proxy: Option<Proxy>,
+        country: CountryCode,
+    ) -> Self {
+        Self::api_endpoint(
+            api_key,
+            api_uid,
+            proxy,
+            ApiEndpoint {
+                country,
+                ..ApiEndpoint::default()
+            },
+        )
+    }
+
+    /// Read dates for postal codes from the REST API at `endpoint`.
+    ///
+    /// Without an explicit `proxy`, the proxy is read from the environment.
+    /// The credentials are only sent over HTTPS.
     #[allow(clippy::missing_panics_doc)]
-    pub fn api(api_key: ApiKey, api_uid: ApiUid) -> Self {
+    pub fn api_endpoint(
+        api_key: ApiKey,
+        api_uid: ApiUid,
+        proxy: Option<Proxy>,
+        endpoint: ApiEndpoint,
+    ) -> Self {
         // Define the middleware function
         let auth = AuthMiddleware {
             api_key: api_key.0,
             api_uid: api_uid.0,
         };
-        let config = Agent::config_builder()
-            .https_only(true)
-            .accept("application/json")
-            .middleware(auth)
-            .build();
-        tracing::debug!("Constructing HTTP agent with config: {config:?}");
-        Self::Api(config.into())
+        // Tests talk to a plain HTTP server on localhost
+        let config = agent_config(proxy, !cfg!(test)).middleware(auth);
+        Self::Api {
+            agent: build_agent(config),
+            endpoint,
+        }
+    }
+
+    /// Read dates from JSON at `url`, as it would be read from a file.
+    ///
+    /// No API credentials are sent. Without an explicit `proxy`, the proxy is
+    /// read from the environment. Plain HTTP is only allowed if `url` uses it.
+    #[must_use]
+    pub fn from_url(proxy: Option<Proxy>, url: Url) -> Self {
+        let config = agent_config(proxy, url.scheme() == "https");
+        Self::Url {
+            agent: build_agent(config),
+            url,
+        }
     }
 
     #[must_use]
-    /// Read dates from file.
-    pub const fn file(path: Option<PathBuf>) -> Self {
-        Self::File(path)
+    /// Read dates from file, or from stdin if `path` is omitted or `-`.
+    pub fn file(path: Option<PathBuf>) -> Self {
+        Self::File(path.filter(|path| path.as_os_str() != "-"))
+    }
+
+    #[must_use]
+    /// Serve `response` without reading a file or the network.
+    pub const fn from_response(response: ApiResponse) -> Self {
+        Self::Memory(response)
     }
 
     #[allow(clippy::missing_errors_doc)]
@@ -84,28 +231,68 @@ impl DeliveryDays {
         &self,
         postal_code: NorwegianPostalCode,
     ) -> Result<::calendar::Calendar, Box<dyn core::error::Error>> {
-        const NAMESPACE: Uuid = uuid::uuid!("fa23afe5-b154-41f2-af5b-3e597f67bae6");
         let response: ApiResponse = self.get(postal_code)?;
         tracing::debug!("Got: {response:?}");
+        Ok(Self::calendar(postal_code, response))
+    }
+
+    /// Build a calendar from an API response.
+    #[must_use]
+    pub fn calendar(
+        postal_code: NorwegianPostalCode,
+        response: ApiResponse,
+    ) -> ::calendar::Calendar {
         let created = Utc::now();
-        let url =
-            Url::parse("https://www.posten.no/levering-av-post/").expect("Should never happen");
-        let cal = Calendar::new(
-            NAMESPACE,
-            response.delivery_dates,
-            postal_code,
-            created,
-            url,
-        );
+        let delivery_dates = response
+            .delivery_dates
+            .into_iter()
+            .map(|date| DeliveryDate::new(postal_code, date))
+            .collect();
+        let cal = Calendar::new(delivery_dates, postal_code, created);
         let cal: ::calendar::Calendar = cal.into();
         // let fractions = response.into_values().collect();
         // let url =
         //     Url::parse("https://www.posten.no/levering-av-post/").expect("Should never happen");
         // let cal: ::calendar::Calendar =
         //     Calendar::new(NAMESPACE, fractions, address, created, url).into();
-        tracing::info!("Exported {} calendar events", cal.events.len());
+        tracing::info!(
+            code = %postal_code,
+            count = cal.events.len(),
+            "Exported calendar events"
+        );
+
+        cal
+    }
+
+    /// The Bring API URL with delivery dates for `postal_code` in
+    /// `country`.
+    #[must_use]
+    pub fn url(country: CountryCode, postal_code: NorwegianPostalCode) -> Url {
+        Self::endpoint_url(
+            &ApiEndpoint {
+                country,
+                ..ApiEndpoint::default()
+            },
+            postal_code,
+        )
+    }
 
-        Ok(cal)
+    /// The API URL at `endpoint` with delivery dates for `postal_code`.
+    #[allow(clippy::missing_panics_doc)]
+    #[must_use]
+    pub fn endpoint_url(endpoint: &ApiEndpoint, postal_code: NorwegianPostalCode) -> Url {
+        let mut url = endpoint
+            .base_url
+            .join(&format!(
+                "{}/postal-codes/{postal_code}/mailbox-delivery-dates",
+                endpoint.country
+            ))
+            .expect("Should never happen");
+        if let Some(from_code) = endpoint.from_code {
+            url.query_pairs_mut()
+                .append_pair("from", &from_code.to_string());
+        }
+        url
     }
 
     /// Get a list of delivery dates.
@@ -114,25 +301,248 @@ impl DeliveryDays {
         &self,
         postal_code: NorwegianPostalCode,
     ) -> Result<T, Box<dyn core::error::Error>> {
-        let response: T = match self {
-            Self::Api(client) => {
-                let url = format!(
-                    "https://api.bring.com/address/api/{NORWAY}/postal-codes/{postal_code}/mailbox-delivery-dates"
-                );
-                tracing::debug!("Using URL: {url}");
-                client.get(url).call()?.body_mut().read_json()?
+        Ok(from_json_reader(self.get_raw(postal_code)?.as_slice())?)
+    }
+
+    /// Get the response body as received, without parsing it.
+    #[allow(clippy::missing_errors_doc)]
+    pub fn get_raw(
+        &self,
+        postal_code: NorwegianPostalCode,
+    ) -> Result<Vec<u8>, Box<dyn core::error::Error>> {
+        let body = match self {
+            Self::Api {
+                agent: client,
+                endpoint,
+            } => {
+                let url = Self::endpoint_url(endpoint, postal_code);
+                tracing::debug!(%url, code = %postal_code, "Fetching delivery dates");
+                read_response(client.get(url.as_str()).call()?)?
+            }
+            Self::Url { agent, url } => {
+                tracing::debug!(%url, "Fetching delivery dates");
+                read_response(agent.get(url.as_str()).call()?)?
             }
             Self::File(Some(path)) => {
-                tracing::debug!("Reading from file: {}", path.display());
-                serde_json::from_reader(
-                    std::fs::File::open(path).map_err(|err| io_error_to_string(&err, path))?,
-                )?
+                tracing::debug!(path = %path.display(), "Reading from file");
+                std::fs::read(path).map_err(|err| io_error_to_string(&err, path))?
             }
             Self::File(None) => {
                 tracing::debug!("Reading from stdin");
-                serde_json::from_reader(std::io::stdin())?
+                let mut buf = Vec::new();
+                std::io::stdin().read_to_end(&mut buf)?;
+                buf
             }
+            Self::Memory(response) => serde_json::to_vec(response)?,
+        };
+        Ok(body)
+    }
+}
+
+/// The configuration of an agent for JSON requests, through `proxy` if
+/// given, otherwise the proxy from the environment.
+fn agent_config(proxy: Option<Proxy>, https_only: bool) -> ConfigBuilder<AgentScope> {
+    let config = Agent::config_builder()
+        .https_only(https_only)
+        .http_status_as_error(false)
+        .accept("application/json");
+    if proxy.is_some() {
+        config.proxy(proxy)
+    } else {
+        config
+    }
+}
+
+fn build_agent(config: ConfigBuilder<AgentScope>) -> Agent {
+    let config = config.build();
+    tracing::debug!("Constructing HTTP agent with config: {config:?}");
+    config.into()
+}
+
+/// The body of a successful response, or an error with the status and the
+/// start of the body.
+fn read_response(mut response: Response<Body>) -> Result<Vec<u8>, Box<dyn core::error::Error>> {
+    const SNIPPET_LEN: usize = 200;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.body_mut().read_to_string().unwrap_or_default();
+        let snippet: String = body.trim().chars().take(SNIPPET_LEN).collect();
+        return Err(format!("HTTP status {}: {snippet}", status.as_u16()).into());
+    }
+    Ok(response.body_mut().read_to_vec()?)
+}
+
+#[cfg(test)]
+mod test {
+    use common::mock::serve_once;
+
+    use super::*;
+
+    #[test]
+    fn test_file_dash_is_stdin() {
+        assert!(matches!(
+            DeliveryDays::file(Some("-".into())),
+            DeliveryDays::File(None)
+        ));
+        assert!(matches!(DeliveryDays::file(None), DeliveryDays::File(None)));
+        assert!(matches!(
+            DeliveryDays::file(Some("./-".into())),
+            DeliveryDays::File(Some(_))
+        ));
+    }
+
+    #[test]
+    fn test_from_response() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let dates = [
+            NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+            NaiveDate::from_ymd_opt(2024, 3, 4).unwrap(),
+        ];
+        let provider = DeliveryDays::from_response(ApiResponse {
+            delivery_dates: dates.to_vec(),
+        });
+        let cal = provider.get_calendar(code).unwrap();
+        assert_eq!(
+            cal.events
+                .iter()
+                .map(|event| event.date)
+                .collect::<Vec<_>>(),
+            dates
+        );
+    }
+
+    #[test]
+    fn test_from_url() {
+        let (base_url, server) = serve_once(r#"{"deliveryDates":["2024-03-01","2024-03-04"]}"#);
+        let url = base_url.join("snapshot.json").unwrap();
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let cal = DeliveryDays::from_url(None, url)
+            .get_calendar(code)
+            .unwrap();
+        assert_eq!(cal.events.len(), 2);
+        let request = server.join().unwrap();
+        assert!(
+            request.starts_with("GET /snapshot.json HTTP/1.1\r\n"),
+            "{request}"
+        );
+        assert!(!request.contains(HEADER_KEY), "{request}");
+    }
+
+    #[test]
+    fn test_api_from_code() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let request = |from_code| {
+            let (base_url, server) = serve_once(r#"{"deliveryDates":["2024-03-01"]}"#);
+            let endpoint = ApiEndpoint {
+                base_url,
+                from_code,
+                ..ApiEndpoint::default()
+            };
+            let provider = DeliveryDays::api_endpoint(
+                ApiKey::try_from("key").unwrap(),
+                ApiUid::try_from("uid@example.com").unwrap(),
+                None,
+                endpoint,
+            );
+            assert_eq!(provider.get_calendar(code).unwrap().events.len(), 1);
+            server.join().unwrap()
         };
-        Ok(response)
+        let request_line = |request: &str| request.lines().next().unwrap().to_string();
+
+        assert_eq!(
+            request_line(&request(NorwegianPostalCode::try_from("0150").ok())),
+            "GET /no/postal-codes/7800/mailbox-delivery-dates?from=0150 HTTP/1.1"
+        );
+        assert_eq!(
+            request_line(&request(None)),
+            "GET /no/postal-codes/7800/mailbox-delivery-dates HTTP/1.1"
+        );
+    }
+
+    #[test]
+    fn test_try_from_value() {
+        let response =
+            ApiResponse::try_from(serde_json::json!({ "deliveryDates": ["2024-03-01"] })).unwrap();
+        assert_eq!(
+            response.delivery_dates,
+            [NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()]
+        );
+        assert!(ApiResponse::try_from(serde_json::json!({ "dates": [] })).is_err());
+    }
+
+    #[test]
+    fn test_url() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        assert_eq!(
+            DeliveryDays::url(CountryCode::try_from("se").unwrap(), code).as_str(),
+            "https://api.bring.com/address/api/se/postal-codes/7800/mailbox-delivery-dates"
+        );
+        let mut endpoint = ApiEndpoint::default();
+        assert_eq!(
+            DeliveryDays::endpoint_url(&endpoint, code).as_str(),
+            "https://api.bring.com/address/api/no/postal-codes/7800/mailbox-delivery-dates"
+        );
+        endpoint.country = CountryCode::try_from("se").unwrap();
+        assert_eq!(
+            DeliveryDays::endpoint_url(&endpoint, code).path(),
+            "/address/api/se/postal-codes/7800/mailbox-delivery-dates"
+        );
+        endpoint.from_code = NorwegianPostalCode::try_from("0150").ok();
+        assert_eq!(
+            DeliveryDays::endpoint_url(&endpoint, code).query(),
+            Some("from=0150")
+        );
+    }
+
+    #[test]
+    fn test_delivery_date_set() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let other = NorwegianPostalCode::try_from("7801").unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let dates: std::collections::HashSet<_> = [
+            DeliveryDate::new(code, date),
+            DeliveryDate::new(code, date),
+            DeliveryDate::new(other, date),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(dates.len(), 2);
+    }
+
+    #[test]
+    fn test_field_spelling() {
+        let dates = |json: &str| {
+            serde_json::from_str::<ApiResponse>(json)
+                .map(|response| response.delivery_dates)
+                .map_err(|err| err.to_string())
+        };
+        let expected = [NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()];
+        assert_eq!(
+            dates(r#"{"delivery_dates":["2024-03-01"]}"#).unwrap(),
+            expected
+        );
+        assert_eq!(
+            dates(r#"{"deliveryDates":["2024-03-01"]}"#).unwrap(),
+            expected
+        );
+        assert!(dates("{}").unwrap_err().contains("delivery_dates"));
+    }
+
+    #[test]
+    fn test_read_response() {
+        let response = |status: u16, body: &str| {
+            Response::builder()
+                .status(status)
+                .body(Body::builder().data(body))
+                .unwrap()
+        };
+        let html = format!("<html><body>{}</body></html>", "x".repeat(500));
+        let err = read_response(response(503, &html)).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("HTTP status 503: <html><body>xxx"),
+            "{err}"
+        );
+        assert_eq!(read_response(response(200, "{}")).unwrap(), b"{}");
     }
 }
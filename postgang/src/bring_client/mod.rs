@@ -5,11 +5,10 @@ use ureq::http::HeaderValue;
 
 const HEADER_UID: &str = "X-Mybring-API-Uid";
 const HEADER_KEY: &str = "X-Mybring-API-Key";
-const NORWAY: &str = "no";
 const INVALID_NORWEGIAN_POST_CODE: &str =
     "Invalid postal code format for Norway. Postal code must be numeric and consist of 4 digits";
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 /// Represents a norwegian postal code.
 ///
 /// Postal codes must be numeric and consist of 4 digits
@@ -53,6 +52,60 @@ impl Display for NorwegianPostalCode {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// ISO 3166-1 alpha-2 code of the country to look up postal codes in.
+///
+/// Postal codes are still validated as [`NorwegianPostalCode`], so only
+/// countries with four digit codes, like Denmark, can be looked up.
+///
+/// ```
+/// use postgang::bring_client::CountryCode;
+/// let country = CountryCode::try_from("SE").unwrap();
+/// assert_eq!(country.to_string(), "se");
+/// assert!(CountryCode::try_from("swe").is_err());
+/// ```
+pub struct CountryCode([u8; 2]);
+
+impl CountryCode {
+    pub const NORWAY: Self = Self(*b"no");
+}
+
+impl Default for CountryCode {
+    fn default() -> Self {
+        Self::NORWAY
+    }
+}
+
+#[derive(Debug)]
+/// A possible error when converting a [`CountryCode`] from a string.
+pub struct InvalidCountryCode;
+
+impl Display for InvalidCountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Country code must be two letters, such as no or se")
+    }
+}
+
+impl<'a> TryFrom<&'a str> for CountryCode {
+    type Error = InvalidCountryCode;
+
+    fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+        match value.as_bytes() {
+            &[a, b] if a.is_ascii_alphabetic() && b.is_ascii_alphabetic() => {
+                Ok(Self([a.to_ascii_lowercase(), b.to_ascii_lowercase()]))
+            }
+            _ => Err(InvalidCountryCode),
+        }
+    }
+}
+
+impl Display for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b] = self.0;
+        write!(f, "{}{}", char::from(a), char::from(b))
+    }
+}
+
 #[derive(Clone)]
 /// API key to be used by the HTTP client.
 ///
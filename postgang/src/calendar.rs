@@ -9,7 +9,7 @@ use chrono::{
 use url::Url;
 use uuid::Uuid;
 
-use crate::bring_client::NorwegianPostalCode;
+use crate::bring_client::{NorwegianPostalCode, mailbox_delivery_dates::DeliveryDate};
 
 fn weekday(date: NaiveDate) -> &'static str {
     match date.weekday() {
@@ -23,13 +23,42 @@ fn weekday(date: NaiveDate) -> &'static str {
     }
 }
 
+const NAMESPACE: Uuid = uuid::uuid!("fa23afe5-b154-41f2-af5b-3e597f67bae6");
+const URL: &str = "https://www.posten.no/levering-av-post/";
+
 #[derive(Debug, Clone)]
 pub struct Calendar {
-    namespace: Uuid,
-    delivery_dates: Vec<NaiveDate>,
+    delivery_dates: Vec<DeliveryDate>,
     postal_code: NorwegianPostalCode,
     created: DateTime<Utc>,
-    url: Url,
+}
+
+impl From<&DeliveryDate> for ::calendar::Event {
+    /// A one day event with a stable UID. The timestamp is the current time.
+    fn from(delivery: &DeliveryDate) -> Self {
+        let code = delivery.postal_code;
+        let date = delivery.date;
+        let weekday = weekday(date);
+        let day = date.day();
+        Self {
+            uid: stable_uid(None, code, date),
+            dtstamp: Utc::now(),
+            sequence: 0,
+            date,
+            summary: format!("📬 {code}: {weekday} {day}."),
+            url: Url::parse(URL).ok(),
+            description: None,
+            duration: NonZeroU8::MIN,
+            rrule: None,
+            rdates: Vec::new(),
+            exdates: Vec::new(),
+            recurrence_id: None,
+            this_and_future: false,
+            geo: None,
+            alarm: None,
+            extensions: Vec::new(),
+        }
+    }
 }
 
 impl From<Calendar> for ::calendar::Calendar {
@@ -43,51 +72,84 @@ impl From<Calendar> for ::calendar::Calendar {
             events: calendar
                 .delivery_dates
                 .iter()
-                .map(move |date| {
-                    let weekday = weekday(*date);
-                    let day = date.day();
-                    let summary = format!("📬 {code}: {weekday} {day}.");
-
-                    ::calendar::Event {
-                        uid: generate_stable_uid(calendar.namespace, calendar.postal_code, *date),
-                        dtstamp: calendar.created,
-                        sequence: calendar.created.timestamp(),
-                        date: *date,
-                        summary,
-                        url: Some(calendar.url.clone()),
-                        description: None,
-                        duration: NonZeroU8::MIN,
-                        rrule: None,
-                        rdates: Vec::new(),
-                        exdates: Vec::new(),
-                        recurrence_id: None,
-                    }
+                .map(|delivery| ::calendar::Event {
+                    dtstamp: calendar.created,
+                    sequence: calendar.created.timestamp(),
+                    ..delivery.into()
                 })
                 .collect(),
+            options: ::calendar::RenderOptions::default(),
+            attendees: Vec::new(),
+            organizer: None,
+            color: None,
+            refresh_interval: None,
+            calscale: Some(::calendar::DEFAULT_CALSCALE.to_string()),
         }
     }
 }
 
+/// Stable UID of a delivery date. A `prefix` gives the event a different
+/// UID, so separate feeds for the same postal code don't collide.
+#[must_use]
+pub fn stable_uid(prefix: Option<&str>, code: NorwegianPostalCode, date: NaiveDate) -> Uuid {
+    let namespace = prefix.map_or(NAMESPACE, |prefix| {
+        Uuid::new_v5(&NAMESPACE, prefix.as_bytes())
+    });
+    generate_stable_uid(namespace, code, date)
+}
+
 fn generate_stable_uid(namespace: Uuid, code: NorwegianPostalCode, date: NaiveDate) -> Uuid {
     let input_data = format!("{date}-{code}");
     Uuid::new_v5(&namespace, input_data.as_bytes())
 }
 
 impl Calendar {
+    /// Delivery dates are sorted to give a stable event order.
     #[must_use]
-    pub const fn new(
-        namespace: Uuid,
-        delivery_dates: Vec<NaiveDate>,
+    pub fn new(
+        mut delivery_dates: Vec<DeliveryDate>,
         postal_code: NorwegianPostalCode,
         created: DateTime<Utc>,
-        url: Url,
     ) -> Self {
+        delivery_dates.sort_unstable();
         Self {
-            namespace,
             delivery_dates,
             postal_code,
             created,
-            url,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_events_are_sorted_by_date() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let dates = ["2024-03-05", "2024-03-01", "2024-03-03"]
+            .iter()
+            .map(|date| DeliveryDate::new(code, date.parse().unwrap()))
+            .collect();
+        let cal: ::calendar::Calendar =
+            Calendar::new(dates, code, DateTime::from_timestamp(0, 0).unwrap()).into();
+        let dates: Vec<String> = cal.events.iter().map(|e| e.date.to_string()).collect();
+        assert_eq!(dates, ["2024-03-01", "2024-03-03", "2024-03-05"]);
+    }
+
+    #[test]
+    fn test_event_from_delivery_date() {
+        let code = NorwegianPostalCode::try_from("7800").unwrap();
+        let date = NaiveDate::from_ymd_opt(2024, 3, 1).unwrap();
+        let event = ::calendar::Event::from(&DeliveryDate::new(code, date));
+        assert_eq!(
+            event.uid,
+            uuid::uuid!("d6e9b363-eeaa-5a86-8b29-cebfa5f4c070")
+        );
+        assert_eq!(event.date, date);
+        assert_eq!(event.summary, "📬 7800: fredag 1.");
+        assert_eq!(event.url.unwrap().as_str(), URL);
+        assert_eq!(event.duration.get(), 1);
+        assert_eq!(event.recurrence_id, None);
+    }
+}
@@ -117,6 +117,7 @@ use core::error::Error;
 use std::process::ExitCode;
 
 use clap::Parser as ClapParser;
+use common::logging::{self, LogArgs};
 
 use postgang::Commands;
 
@@ -124,22 +125,25 @@ use postgang::Commands;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[command(flatten)]
+    log: LogArgs,
 }
 
-fn try_main() -> Result<(), Box<dyn Error>> {
-    let cli = Cli::parse();
+fn try_main(cli: Cli) -> Result<(), Box<dyn Error>> {
     tracing::debug!("Got CLI args: {cli:?}");
     cli.command.run()
 }
 
 fn main() -> ExitCode {
-    match try_main() {
+    let cli = Cli::parse();
+    let _logger_guard = logging::init(cli.log.log_format, "postgang.log");
+    match try_main(cli) {
         Ok(()) => {
             tracing::info!("Success");
             ExitCode::SUCCESS
         }
         Err(err) => {
-            tracing::error!("{err}");
+            tracing::error!(error = %err, "Command failed");
             ExitCode::FAILURE
         }
     }